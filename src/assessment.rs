@@ -0,0 +1,550 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use crate::rubric::Rubric;
+
+/// Key of the overall difficulty in the YAML answer format.
+pub const OVERALL_KEY: &str = "Overall";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Category {
+    pub name: String,
+    pub notes: Vec<String>,
+    /// One of the rubric's ratings, see `Rubric::normalize_rating`.
+    pub rating: String,
+    /// Keys the LLM wrote besides Note and Rating, such as Confidence or Evidence.
+    #[serde(default, skip_serializing_if = "Mapping::is_empty")]
+    pub extra: Mapping,
+}
+
+/// Parsed assessment, shared by conversion, validation and export.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Assessment {
+    pub categories: Vec<Category>,
+    pub overall: Option<String>,
+    /// Top-level sections that are neither a category nor Overall, such as a Summary.
+    #[serde(default, skip_serializing_if = "Mapping::is_empty")]
+    pub extra_sections: Mapping,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AssessmentFormat {
+    Yaml,
+    Markdown,
+}
+
+impl AssessmentFormat {
+    /// YAML if the text parses to a mapping, Markdown otherwise. Markdown headings are YAML
+    /// comments and bullets are a YAML sequence, so the two do not overlap in practice.
+    pub fn detect(text: &str) -> Self {
+        match serde_yaml::from_str::<Value>(text) {
+            Ok(Value::Mapping(_)) => AssessmentFormat::Yaml,
+            _ => AssessmentFormat::Markdown,
+        }
+    }
+}
+
+/// One category as the LLM writes it in YAML.
+#[derive(Deserialize)]
+struct YamlSection {
+    #[serde(rename = "Note", default)]
+    notes: Vec<String>,
+    #[serde(rename = "Rating")]
+    rating: String,
+    #[serde(flatten)]
+    extra: Mapping,
+}
+
+impl Assessment {
+    /// Parse either answer format, detected with `AssessmentFormat::detect`. Ratings are
+    /// normalised but not checked against the rubric, see `validate`.
+    pub fn parse(text: &str, rubric: &Rubric) -> Result<Self> {
+        match AssessmentFormat::detect(text) {
+            AssessmentFormat::Yaml => Self::from_yaml(text, rubric),
+            AssessmentFormat::Markdown => Self::from_markdown(text, rubric),
+        }
+    }
+
+    /// Parse the YAML answer format (`Experience: {Note: [...], Rating: ...}`, ..., `Overall: ...`).
+    /// Categories come out in rubric order, followed by any other section the LLM added.
+    pub fn from_yaml(yaml_str: &str, rubric: &Rubric) -> Result<Self> {
+        let yaml: Value = serde_yaml::from_str(yaml_str)
+            .map_err(|e| anyhow!("Invalid YAML format: {}", e))?;
+        let yaml_map: Mapping = match yaml {
+            Value::Mapping(map) => map,
+            _ => return Err(anyhow!("Invalid YAML structure: expected mapping at root")),
+        };
+
+        let mut overall = None;
+        let mut sections = Vec::new();
+        let mut extra_sections = Mapping::new();
+        for (key, value) in yaml_map {
+            let name = key.as_str()
+                .ok_or_else(|| anyhow!("Invalid section name"))?
+                .to_string();
+            if name == OVERALL_KEY {
+                let overall_rating = serde_yaml::from_value::<String>(value)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|text| rubric.normalize_rating(&text))
+                    .with_context(|| format!("Invalid {} rating", OVERALL_KEY))?;
+                overall = Some(overall_rating);
+                continue;
+            }
+
+            // Anything that is not a rubric category and carries no rating is kept as is
+            let rated = value.as_mapping().is_some_and(|map| map.contains_key("Rating"));
+            if !rated && !rubric.category_names().contains(&name.as_str()) {
+                extra_sections.insert(Value::String(name), value);
+                continue;
+            }
+
+            let section: YamlSection = serde_yaml::from_value(value)
+                .with_context(|| format!("Invalid section \"{}\"", name))?;
+            let rating = rubric.normalize_rating(&section.rating)
+                .with_context(|| format!("Invalid section \"{}\"", name))?;
+            sections.push(Category {
+                name,
+                notes: section.notes,
+                rating,
+                extra: section.extra,
+            });
+        }
+
+        Ok(Self {
+            categories: order_by_rubric(sections, rubric)?,
+            overall,
+            extra_sections,
+        })
+    }
+
+    /// Parse the Markdown answer format the request asks for. Headings may be written with or
+    /// without `#` and with or without a trailing colon; the overall difficulty may be on the
+    /// heading line (`Overall: Medium`) or below it (`Difficulty Medium`).
+    pub fn from_markdown(markdown: &str, rubric: &Rubric) -> Result<Self> {
+        let mut overall = None;
+        let mut in_overall = false;
+        let mut sections: Vec<(String, Vec<String>, Option<String>)> = Vec::new();
+
+        for (line_no, raw_line) in markdown.lines().enumerate() {
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with("```") {
+                continue;
+            }
+
+            if let Some((name, rest)) = section_heading(line, rubric) {
+                in_overall = name == OVERALL_KEY;
+                if in_overall {
+                    if !rest.is_empty() {
+                        overall = Some(parse_overall(rest, rubric)?);
+                    }
+                } else {
+                    sections.push((name, Vec::new(), None));
+                }
+                continue;
+            }
+
+            if in_overall {
+                overall = Some(parse_overall(line, rubric)?);
+                continue;
+            }
+
+            let (_, notes, rating) = sections.last_mut().ok_or_else(|| {
+                anyhow!("Line {}: unexpected text before the first section: \"{}\"", line_no + 1, line)
+            })?;
+            let item = line.trim_start_matches(['-', '*', '+']).trim_start();
+            let rating_value = strip_prefix_ignore_case(item, "Rating")
+                .and_then(|rest| rest.trim_start().strip_prefix(':'));
+            match rating_value {
+                Some(value) => *rating = Some(rubric.normalize_rating(value)?),
+                None => notes.push(item.to_string()),
+            }
+        }
+
+        let mut categories = Vec::with_capacity(sections.len());
+        let mut extra_sections = Mapping::new();
+        for (name, notes, rating) in sections {
+            match rating {
+                Some(rating) => categories.push(Category { name, notes, rating, extra: Mapping::new() }),
+                None if !rubric.category_names().contains(&name.as_str()) => {
+                    let notes = notes.into_iter().map(Value::String).collect();
+                    extra_sections.insert(Value::String(name), Value::Sequence(notes));
+                }
+                None => return Err(anyhow!("Section \"{}\" has no Rating", name)),
+            }
+        }
+
+        Ok(Self {
+            categories: order_by_rubric(categories, rubric)?,
+            overall,
+            extra_sections,
+        })
+    }
+
+    /// Check every rating against the rubric: category ratings against the rating scale and the
+    /// overall difficulty against the (usually stricter) overall levels.
+    pub fn validate(&self, rubric: &Rubric) -> Result<()> {
+        let mut problems = Vec::new();
+        for category in &self.categories {
+            if !rubric.ratings.contains(&category.rating) {
+                problems.push(format!(
+                    "{}: rating \"{}\" is not one of {}",
+                    category.name, category.rating, rubric.ratings.join(", ")
+                ));
+            }
+        }
+        if let Some(overall) = &self.overall {
+            if !rubric.overall_names().contains(&overall.as_str()) {
+                problems.push(format!(
+                    "{}: difficulty \"{}\" is not one of {}",
+                    OVERALL_KEY, overall, rubric.overall_names().join(", ")
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Ratings outside the rubric scale:\n{}", problems.join("\n")))
+        }
+    }
+
+    /// Everything that is not part of the rubric format: extra keys inside categories
+    /// (`Experience / Confidence`) and extra top-level sections (`Summary`).
+    pub fn unknown_keys(&self) -> Vec<String> {
+        let mut keys = Vec::new();
+        for category in &self.categories {
+            for key in category.extra.keys() {
+                keys.push(format!("{} / {}", category.name, scalar_text(key)));
+            }
+        }
+        keys.extend(self.extra_sections.keys().map(scalar_text));
+        keys
+    }
+
+    /// Render in the Markdown answer format. With `keep_unknown`, keys and sections outside the
+    /// rubric are rendered too, nested sequences and mappings as indented bullets.
+    pub fn to_markdown(&self, keep_unknown: bool) -> String {
+        let mut markdown = String::new();
+        for category in &self.categories {
+            markdown.push_str(&format!("# {}\n", category.name));
+            for note in &category.notes {
+                markdown.push_str(&format!("- {}\n", note));
+            }
+            if keep_unknown {
+                render_mapping(&mut markdown, &category.extra, 0);
+            }
+            markdown.push_str(&format!("- Rating: {}\n", category.rating));
+            markdown.push('\n');
+        }
+
+        if keep_unknown {
+            for (key, value) in &self.extra_sections {
+                markdown.push_str(&format!("# {}\n", scalar_text(key)));
+                match value {
+                    Value::Mapping(map) => render_mapping(&mut markdown, map, 0),
+                    Value::Sequence(items) => render_sequence(&mut markdown, items, 0),
+                    scalar => markdown.push_str(&format!("- {}\n", scalar_text(scalar))),
+                }
+                markdown.push('\n');
+            }
+        }
+
+        if let Some(overall) = &self.overall {
+            markdown.push_str(&format!("# {}\n Difficulty {}\n", OVERALL_KEY, overall));
+        }
+        markdown
+    }
+
+    /// Render in the YAML answer format (`Note`/`Rating` per category, then `Overall`), so the
+    /// result parses back with `from_yaml`.
+    pub fn to_yaml(&self, keep_unknown: bool) -> Result<String> {
+        let mut yaml = Mapping::new();
+        for category in &self.categories {
+            let mut section = Mapping::new();
+            section.insert("Note".into(), category.notes.iter().map(|n| Value::from(n.as_str())).collect());
+            if keep_unknown {
+                section.extend(category.extra.clone());
+            }
+            section.insert("Rating".into(), category.rating.as_str().into());
+            yaml.insert(category.name.as_str().into(), Value::Mapping(section));
+        }
+        if keep_unknown {
+            yaml.extend(self.extra_sections.clone());
+        }
+        if let Some(overall) = &self.overall {
+            yaml.insert(OVERALL_KEY.into(), overall.as_str().into());
+        }
+        Ok(serde_yaml::to_string(&yaml)?)
+    }
+}
+
+fn render_mapping(markdown: &mut String, map: &Mapping, indent: usize) {
+    for (key, value) in map {
+        let pad = " ".repeat(indent);
+        match value {
+            Value::Mapping(inner) => {
+                markdown.push_str(&format!("{}- {}:\n", pad, scalar_text(key)));
+                render_mapping(markdown, inner, indent + 2);
+            }
+            Value::Sequence(items) => {
+                markdown.push_str(&format!("{}- {}:\n", pad, scalar_text(key)));
+                render_sequence(markdown, items, indent + 2);
+            }
+            scalar => markdown.push_str(&format!("{}- {}: {}\n", pad, scalar_text(key), scalar_text(scalar))),
+        }
+    }
+}
+
+fn render_sequence(markdown: &mut String, items: &[Value], indent: usize) {
+    for item in items {
+        match item {
+            Value::Mapping(inner) => render_mapping(markdown, inner, indent),
+            Value::Sequence(inner) => render_sequence(markdown, inner, indent + 2),
+            scalar => markdown.push_str(&format!("{}- {}\n", " ".repeat(indent), scalar_text(scalar))),
+        }
+    }
+}
+
+fn scalar_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => serde_yaml::to_string(other)
+            .map(|s| s.trim_end().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+/// Section name and the text following it on the heading line, if `line` starts a section.
+/// `#` headings always start one; bare lines only when they name a rubric category or Overall.
+fn section_heading<'a>(line: &'a str, rubric: &Rubric) -> Option<(String, &'a str)> {
+    let hashed = line.starts_with('#');
+    let (title, rest) = split_heading(line);
+
+    let known = rubric.category_names().into_iter()
+        .chain([OVERALL_KEY])
+        .find(|name| name.eq_ignore_ascii_case(title));
+    match known {
+        Some(name) if name == OVERALL_KEY || rest.is_empty() => Some((name.to_string(), rest)),
+        Some(_) => None,
+        None if hashed && !title.is_empty() => Some((title.to_string(), rest)),
+        None => None,
+    }
+}
+
+/// Split a heading-like line into its title and whatever follows the colon, ignoring `#`
+/// markers and `**bold**` emphasis: `## **Overall:** Hard` gives `("Overall", "Hard")`.
+pub(crate) fn split_heading(line: &str) -> (&str, &str) {
+    let text = line.trim().trim_start_matches('#').trim();
+    let (title, rest) = text.split_once(':').unwrap_or((text, ""));
+    (
+        title.trim().trim_start_matches("**").trim_end_matches("**").trim(),
+        rest.trim_start_matches("**").trim(),
+    )
+}
+
+/// Overall value with the optional `Difficulty` word in front of it.
+fn parse_overall(text: &str, rubric: &Rubric) -> Result<String> {
+    let value = strip_prefix_ignore_case(text, "Difficulty").unwrap_or(text);
+    rubric.normalize_rating(value.trim_start_matches(':'))
+        .with_context(|| format!("Invalid {} rating", OVERALL_KEY))
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = text.get(..prefix.len())?;
+    if head.eq_ignore_ascii_case(prefix) {
+        Some(&text[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+/// Put rubric categories first, in rubric order, and fail if any of them is missing.
+fn order_by_rubric(mut sections: Vec<Category>, rubric: &Rubric) -> Result<Vec<Category>> {
+    let mut ordered = Vec::with_capacity(sections.len());
+    let mut missing = Vec::new();
+    for name in rubric.category_names() {
+        match sections.iter().position(|c| c.name == name) {
+            Some(i) => ordered.push(sections.remove(i)),
+            None => missing.push(name),
+        }
+    }
+    if !missing.is_empty() {
+        return Err(anyhow!("Missing rubric categories: {}", missing.join(", ")));
+    }
+
+    ordered.append(&mut sections);
+    Ok(ordered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_YAML: &str = "
+Knowledge:
+  Note:
+    - Both Math knowledge and Rust knowledge is required
+  Rating: Medium
+Experience:
+  Note:
+    - Need experience about optimizing Rust calculation
+  Rating: Easy - Medium
+Ambiguity:
+  Rating: Medium
+Complexity:
+  Note: []
+  Rating: Very Hard
+Overall: Hard
+";
+
+    #[test]
+    fn test_from_yaml_to_markdown() {
+        let assessment = Assessment::from_yaml(SAMPLE_YAML, &Rubric::builtin()).unwrap();
+        assert_eq!(assessment.categories[0].name, "Experience");
+        assert_eq!(assessment.categories[3].rating, "Very Hard");
+        assert_eq!(assessment.overall.as_deref(), Some("Hard"));
+        assert_eq!(assessment.to_markdown(true), "\
+# Experience
+- Need experience about optimizing Rust calculation
+- Rating: Easy - Medium
+
+# Knowledge
+- Both Math knowledge and Rust knowledge is required
+- Rating: Medium
+
+# Ambiguity
+- Rating: Medium
+
+# Complexity
+- Rating: Very Hard
+
+# Overall
+ Difficulty Hard
+");
+    }
+
+    #[test]
+    fn test_from_markdown_variations() {
+        // Layout of the sample in the request template: only the first heading has `#`
+        let markdown = "\
+# Experience
+ - Need experience about optimizing Rust calculation
+ - Rating: Medium
+Knowledge
+ - Both Math knowledge and Rust knowledge is required
+ - Rating: Medium
+Ambiguity
+ - Prompt is clear on the point, overall goal, and even included what to avoid
+ - Provided code is long
+ - Rating: Medium
+Complexity:
+ - Have to use non standard library or some high level optimization
+ - rating: Medium - Hard
+Overall:
+  Difficulty Medium
+";
+        let assessment = Assessment::from_markdown(markdown, &Rubric::builtin()).unwrap();
+        assert_eq!(assessment.categories.len(), 4);
+        assert_eq!(assessment.categories[2].notes.len(), 2);
+        assert_eq!(assessment.categories[3].rating, "Medium - Hard");
+        assert_eq!(assessment.overall.as_deref(), Some("Medium"));
+
+        let inline = markdown.replace("Overall:\n  Difficulty Medium", "Overall: Hard");
+        let assessment = Assessment::from_markdown(&inline, &Rubric::builtin()).unwrap();
+        assert_eq!(assessment.overall.as_deref(), Some("Hard"));
+    }
+
+    #[test]
+    fn test_format_detection() {
+        assert_eq!(AssessmentFormat::detect(SAMPLE_YAML), AssessmentFormat::Yaml);
+        let assessment = Assessment::from_yaml(SAMPLE_YAML, &Rubric::builtin()).unwrap();
+        assert_eq!(AssessmentFormat::detect(&assessment.to_markdown(true)), AssessmentFormat::Markdown);
+        assert_eq!(AssessmentFormat::detect("Experience\n - Rating: Easy"), AssessmentFormat::Markdown);
+    }
+
+    #[test]
+    fn test_markdown_round_trip() {
+        let assessment = Assessment::from_yaml(SAMPLE_YAML, &Rubric::builtin()).unwrap();
+        let reparsed = Assessment::from_markdown(&assessment.to_markdown(true), &Rubric::builtin()).unwrap();
+        assert_eq!(reparsed, assessment);
+    }
+
+    #[test]
+    fn test_yaml_round_trip() {
+        let assessment = Assessment::from_yaml(SAMPLE_YAML, &Rubric::builtin()).unwrap();
+        let yaml = assessment.to_yaml(true).unwrap();
+        assert!(yaml.starts_with("Experience:\n  Note:\n"), "{}", yaml);
+        assert_eq!(Assessment::from_yaml(&yaml, &Rubric::builtin()).unwrap(), assessment);
+    }
+
+    #[test]
+    fn test_normalised_ratings_and_validation() {
+        let rubric = Rubric::builtin();
+        let messy = SAMPLE_YAML
+            .replace("Rating: Easy - Medium", "Rating: easy–medium")
+            .replace("Rating: Very Hard", "Rating: very-hard")
+            .replace("Overall: Hard", "Overall: Moderate");
+        let assessment = Assessment::from_yaml(&messy, &rubric).unwrap();
+        assert_eq!(assessment.categories[0].rating, "Easy - Medium");
+        assert_eq!(assessment.overall.as_deref(), Some("Medium"));
+        assert!(assessment.validate(&rubric).is_ok());
+
+        let too_hard = SAMPLE_YAML.replace("Overall: Hard", "Overall: Medium - Hard");
+        let err = Assessment::from_yaml(&too_hard, &rubric).unwrap().validate(&rubric).unwrap_err();
+        assert!(err.to_string().contains("Overall: difficulty \"Medium - Hard\" is not one of Easy, Medium, Hard"));
+    }
+
+    #[test]
+    fn test_rubric_levels() {
+        // Levels of the rubric's own, including a word the built-in scale reads as Easy
+        let rubric = Rubric::from_yaml("
+categories: [{name: Experience, label: Experience level}, {name: Security, label: Attack surface}]
+ratings: [Trivial, Routine, Expert]
+overall: [{name: Routine}, {name: Expert}]
+").unwrap();
+        let yaml = "Experience:\n  Rating: trivial\nSecurity:\n  Rating: EXPERT\nOverall: Routine\n";
+        let assessment = Assessment::from_yaml(yaml, &rubric).unwrap();
+        assert_eq!(assessment.categories[0].rating, "Trivial");
+        assert_eq!(assessment.categories[1].rating, "Expert");
+        assert!(assessment.validate(&rubric).is_ok());
+
+        let built_in = Assessment::from_yaml(&yaml.replace("trivial", "Easy"), &rubric).unwrap();
+        let err = built_in.validate(&rubric).unwrap_err();
+        assert!(err.to_string().contains("Experience: rating \"Easy\" is not one of Trivial, Routine, Expert"), "{}", err);
+    }
+
+    #[test]
+    fn test_unknown_keys_are_preserved() {
+        let rubric = Rubric::builtin();
+        let yaml = SAMPLE_YAML.replace("Ambiguity:\n  Rating: Medium", "\
+Ambiguity:
+  Rating: Medium
+  Confidence: High
+  Evidence:
+    - Prompt names the file
+    - Sources:
+        primary: prompt")
+            + "Summary: Mostly a Rust question\n";
+        let assessment = Assessment::from_yaml(&yaml, &rubric).unwrap();
+        assert_eq!(assessment.unknown_keys(), vec!["Ambiguity / Confidence", "Ambiguity / Evidence", "Summary"]);
+
+        let markdown = assessment.to_markdown(true);
+        assert!(markdown.contains("# Ambiguity\n- Confidence: High\n- Evidence:\n  - Prompt names the file\n  \
+            - Sources:\n    - primary: prompt\n- Rating: Medium\n"), "{}", markdown);
+        assert!(markdown.contains("# Summary\n- Mostly a Rust question\n\n# Overall"), "{}", markdown);
+        assert!(!assessment.to_markdown(false).contains("Confidence"));
+    }
+
+    #[test]
+    fn test_from_yaml_errors() {
+        let rubric = Rubric::builtin();
+        let unknown = SAMPLE_YAML.replace("Rating: Very Hard", "Rating: Impossible");
+        let err = format!("{:#}", Assessment::from_yaml(&unknown, &rubric).unwrap_err());
+        assert!(err.contains("Invalid section \"Complexity\""), "{}", err);
+
+        let missing = SAMPLE_YAML.replace("Ambiguity:", "Clarity:");
+        let err = Assessment::from_yaml(&missing, &rubric).unwrap_err().to_string();
+        assert_eq!(err, "Missing rubric categories: Ambiguity");
+    }
+}
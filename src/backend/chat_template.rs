@@ -0,0 +1,215 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use minijinja::{context, Environment, ErrorKind};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use super::Message;
+
+const LLAMA3: &str = "{{ bos_token }}{% for message in messages %}\
+<|start_header_id|>{{ message.role }}<|end_header_id|>\n\n{{ message.content | trim }}<|eot_id|>\
+{% endfor %}{% if add_generation_prompt %}<|start_header_id|>assistant<|end_header_id|>\n\n{% endif %}";
+
+/// The system message goes inside the first `[INST]` block, as Llama 2 expects it.
+const INST: &str = "{% if messages[0].role == 'system' %}\
+{% set system = messages[0].content %}{% set messages = messages[1:] %}{% endif %}\
+{% for message in messages %}{% if message.role == 'user' %}\
+{{ bos_token }}[INST] {% if loop.first and system is defined %}<<SYS>>\n{{ system }}\n<</SYS>>\n\n{% endif %}\
+{{ message.content | trim }} [/INST]{% else %} {{ message.content | trim }} {{ eos_token }}{% endif %}{% endfor %}";
+
+const CHATML: &str = "{% for message in messages %}\
+<|im_start|>{{ message.role }}\n{{ message.content }}<|im_end|>\n\
+{% endfor %}{% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}";
+
+/// How a conversation is written out for a backend that takes a raw prompt.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChatFormat {
+    /// The request as it is; a longer conversation gets a heading per turn, see `flatten`.
+    #[default]
+    Plain,
+    /// Llama 3 header tokens.
+    Llama3,
+    /// `[INST]` blocks of Llama 2 and Mistral.
+    Inst,
+    /// `<|im_start|>` blocks of Qwen and the other ChatML models.
+    ChatMl,
+    /// The `chat_template` of a model's `tokenizer_config.json`.
+    TokenizerConfig,
+}
+
+impl ChatFormat {
+    pub const ALL: [ChatFormat; 5] = [
+        ChatFormat::Plain,
+        ChatFormat::Llama3,
+        ChatFormat::Inst,
+        ChatFormat::ChatMl,
+        ChatFormat::TokenizerConfig,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ChatFormat::Plain => "Plain text",
+            ChatFormat::Llama3 => "Llama 3",
+            ChatFormat::Inst => "[INST] (Llama 2, Mistral)",
+            ChatFormat::ChatMl => "ChatML",
+            ChatFormat::TokenizerConfig => "tokenizer_config.json",
+        }
+    }
+}
+
+/// Chat format settings of the raw prompt backends.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatTemplateSettings {
+    pub format: ChatFormat,
+    /// Read for `ChatFormat::TokenizerConfig`.
+    pub tokenizer_config: Option<PathBuf>,
+    /// System message put before the conversation, none when empty.
+    pub system: String,
+}
+
+impl ChatTemplateSettings {
+    /// The template of the selected format, `None` for plain text.
+    pub fn template(&self) -> Result<Option<ChatTemplate>> {
+        Ok(Some(match self.format {
+            ChatFormat::Plain => return Ok(None),
+            ChatFormat::Llama3 => ChatTemplate::new(LLAMA3, "<|begin_of_text|>", "<|eot_id|>"),
+            ChatFormat::Inst => ChatTemplate::new(INST, "<s>", "</s>"),
+            ChatFormat::ChatMl => ChatTemplate::new(CHATML, "", "<|im_end|>"),
+            ChatFormat::TokenizerConfig => {
+                let path = self.tokenizer_config.as_deref()
+                    .ok_or_else(|| anyhow!("No tokenizer_config.json selected for the chat template"))?;
+                ChatTemplate::from_tokenizer_config(path)?
+            }
+        }))
+    }
+
+    /// The conversation, after the system message, as the prompt to send.
+    pub fn render(&self, messages: &[Message]) -> Result<String> {
+        let mut conversation = Vec::new();
+        if !self.system.trim().is_empty() {
+            conversation.push(Message::system(&self.system));
+        }
+        conversation.extend_from_slice(messages);
+        match self.template()? {
+            Some(template) => template.render(&conversation),
+            None => Ok(super::flatten(&conversation)),
+        }
+    }
+}
+
+/// A Hugging Face style Jinja chat template with the special tokens it refers to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChatTemplate {
+    source: String,
+    bos_token: String,
+    eos_token: String,
+}
+
+impl ChatTemplate {
+    pub fn new(source: &str, bos_token: &str, eos_token: &str) -> Self {
+        Self { source: source.to_string(), bos_token: bos_token.to_string(), eos_token: eos_token.to_string() }
+    }
+
+    /// The `chat_template` of a local `tokenizer_config.json`. A config listing several named
+    /// templates gives its `default` one.
+    pub fn from_tokenizer_config(path: &Path) -> Result<Self> {
+        let json_str = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Value = serde_json::from_str(&json_str)
+            .with_context(|| format!("Invalid JSON in {}", path.display()))?;
+        let source = match &config["chat_template"] {
+            Value::String(source) => Some(source.as_str()),
+            Value::Array(templates) => templates.iter()
+                .find(|template| template["name"] == "default")
+                .or_else(|| templates.first())
+                .and_then(|template| template["template"].as_str()),
+            _ => None,
+        }.ok_or_else(|| anyhow!("{} has no chat_template", path.display()))?;
+        Ok(Self::new(source, special_token(&config["bos_token"]), special_token(&config["eos_token"])))
+    }
+
+    /// Render `messages` followed by the opening of the assistant's turn.
+    pub fn render(&self, messages: &[Message]) -> Result<String> {
+        // The same environment the transformers library renders chat templates in
+        let mut env = Environment::new();
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        minijinja_contrib::add_to_environment(&mut env);
+        env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+        env.add_function("raise_exception", |message: String| -> Result<String, minijinja::Error> {
+            Err(minijinja::Error::new(ErrorKind::InvalidOperation, message))
+        });
+        env.render_str(&self.source, context! {
+            messages => messages,
+            bos_token => &self.bos_token,
+            eos_token => &self.eos_token,
+            add_generation_prompt => true,
+        }).map_err(|e| anyhow!("Failed to render the chat template: {:#}", e))
+    }
+}
+
+/// A special token of `tokenizer_config.json`, given as a string or as an added token object.
+fn special_token(value: &Value) -> &str {
+    value.as_str()
+        .or_else(|| value["content"].as_str())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn conversation() -> Vec<Message> {
+        vec![Message::user("rate this"), Message::assistant("# Experience"), Message::user("again")]
+    }
+
+    #[test]
+    fn test_presets() {
+        let mut settings = ChatTemplateSettings { format: ChatFormat::Llama3, ..Default::default() };
+        assert_eq!(settings.render(&[Message::user(" rate this\n")]).unwrap(),
+                   "<|begin_of_text|><|start_header_id|>user<|end_header_id|>\n\nrate this<|eot_id|>\
+                   <|start_header_id|>assistant<|end_header_id|>\n\n");
+
+        settings.format = ChatFormat::Inst;
+        settings.system = "Be fair.".to_string();
+        assert_eq!(settings.render(&conversation()).unwrap(),
+                   "<s>[INST] <<SYS>>\nBe fair.\n<</SYS>>\n\nrate this [/INST] # Experience </s><s>[INST] again [/INST]");
+
+        settings.format = ChatFormat::ChatMl;
+        assert_eq!(settings.render(&[Message::user("rate this")]).unwrap(),
+                   "<|im_start|>system\nBe fair.<|im_end|>\n<|im_start|>user\nrate this<|im_end|>\n<|im_start|>assistant\n");
+
+        settings.format = ChatFormat::Plain;
+        settings.system.clear();
+        assert_eq!(settings.render(&[Message::user("rate this")]).unwrap(), "rate this");
+    }
+
+    #[test]
+    fn test_tokenizer_config() {
+        let dir = TestDir::new("tokenizer_config");
+        let path = dir.join("tokenizer_config.json");
+        let settings = ChatTemplateSettings {
+            format: ChatFormat::TokenizerConfig,
+            tokenizer_config: Some(path.clone()),
+            system: String::new(),
+        };
+        assert!(settings.render(&conversation()).is_err());
+
+        // Templates lean on Python string methods and raise_exception
+        fs::write(&path, r#"{
+            "bos_token": {"content": "<s>", "lstrip": false},
+            "eos_token": "</s>",
+            "chat_template": [
+                {"name": "tool_use", "template": "unused"},
+                {"name": "default", "template": "{{ bos_token }}{% for message in messages %}{% if message.role not in ['user', 'assistant'] %}{{ raise_exception('Unknown role') }}{% endif %}<{{ message.role | upper }}>{{ message.content.strip() }}{% endfor %}{% if add_generation_prompt %}<ASSISTANT>{% endif %}"}
+            ]
+        }"#).unwrap();
+        assert_eq!(settings.render(&conversation()).unwrap(),
+                   "<s><USER>rate this<ASSISTANT># Experience<USER>again<ASSISTANT>");
+        let with_system = ChatTemplateSettings { system: "Be fair.".to_string(), ..settings };
+        let error = with_system.render(&conversation()).unwrap_err();
+        assert!(format!("{:#}", error).contains("Unknown role"));
+    }
+}
@@ -0,0 +1,290 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use super::{Backend, CancelToken, ChatTemplateSettings, Message};
+
+/// How often the running command is checked for exit, timeout and cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long output is still read after the shell exits. Anything left running in the
+/// background past that is killed, so it cannot hold the reply back until the timeout.
+const EXIT_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommandSettings {
+    /// Command line run through the system shell, e.g.
+    /// `llama-cli -m model.gguf --no-display-prompt -f /dev/stdin`.
+    pub command: String,
+    pub timeout_secs: u64,
+    /// How the conversation is written to stdin.
+    pub chat_template: ChatTemplateSettings,
+}
+
+impl Default for CommandSettings {
+    fn default() -> Self {
+        Self {
+            command: String::new(),
+            timeout_secs: 600,
+            chat_template: ChatTemplateSettings::default(),
+        }
+    }
+}
+
+/// Runs a local model binary: the request goes to its stdin and its stdout is the reply.
+pub struct CommandBackend {
+    settings: CommandSettings,
+}
+
+impl CommandBackend {
+    pub fn new(settings: CommandSettings) -> Self {
+        Self { settings }
+    }
+}
+
+impl Backend for CommandBackend {
+    /// Run the command to completion, passing each line of its output to `on_token` as it is
+    /// printed. It is killed when `cancel` is set or the timeout passes, and whatever it leaves
+    /// running in the background is killed shortly after it exits.
+    fn send(&self, messages: &[Message], on_token: &mut dyn FnMut(&str), cancel: &CancelToken) -> Result<String> {
+        let command = self.settings.command.trim();
+        if command.is_empty() {
+            return Err(anyhow!("No command configured"));
+        }
+        let request = self.settings.chat_template.render(messages)?;
+
+        let mut child = shell(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to start `{}`", command))?;
+
+        // Feed and drain the pipes on their own threads so a full pipe cannot block the child
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let writer = thread::spawn(move || stdin.write_all(request.as_bytes()));
+        let stdout = read_lines(child.stdout.take().expect("stdout is piped"));
+        let stderr = read_all(child.stderr.take().expect("stderr is piped"));
+
+        let started = Instant::now();
+        let timeout = Duration::from_secs(self.settings.timeout_secs);
+        let check = |child: &mut Child| {
+            if cancel.is_cancelled() {
+                kill(child);
+                return Err(anyhow!("Cancelled"));
+            }
+            if started.elapsed() >= timeout {
+                kill(child);
+                return Err(anyhow!("`{}` timed out after {}s", command, self.settings.timeout_secs));
+            }
+            Ok(())
+        };
+        let mut reply = String::new();
+        let status = loop {
+            for line in stdout.try_iter() {
+                on_token(&line);
+                reply.push_str(&line);
+            }
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            check(&mut child)?;
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        // A command that exits without reading all of stdin is fine, it just did not need it.
+        // The writer is not waited for, as a process left in the background may hold stdin.
+        drop(writer);
+        // The output ends once every process holding it closed it, which is after the exit, or
+        // after the grace when the command left something running in the background
+        let exited = Instant::now();
+        let mut killed = false;
+        loop {
+            match stdout.recv_timeout(POLL_INTERVAL) {
+                Ok(line) => {
+                    on_token(&line);
+                    reply.push_str(&line);
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            if !killed && exited.elapsed() >= EXIT_GRACE {
+                kill(&mut child);
+                killed = true;
+            }
+            check(&mut child)?;
+        }
+        if !status.success() {
+            let stderr = loop {
+                match stderr.recv_timeout(POLL_INTERVAL) {
+                    Ok(stderr) => break stderr,
+                    Err(RecvTimeoutError::Disconnected) => break String::new(),
+                    Err(RecvTimeoutError::Timeout) => check(&mut child)?,
+                }
+            };
+            return Err(anyhow!("`{}` exited with {}: {}", command, status, stderr.trim()));
+        }
+        Ok(reply)
+    }
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.args(["/C", command]);
+    shell
+}
+
+/// The shell leads a process group of its own, so `kill` reaches every process it starts.
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    use std::os::unix::process::CommandExt;
+    let mut shell = Command::new("sh");
+    shell.args(["-c", command]).process_group(0);
+    shell
+}
+
+/// Kill the process tree under `cmd`. Processes whose parent already exited are no longer part
+/// of the tree and are left running, as Windows has no process group to reach them through.
+#[cfg(windows)]
+fn kill(child: &mut Child) {
+    let killed = Command::new("taskkill")
+        .args(["/T", "/F", "/PID", &child.id().to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    if !killed.is_ok_and(|status| status.success()) {
+        let _ = child.kill();
+    }
+    let _ = child.wait();
+}
+
+/// Kill the shell's whole process group, e.g. the model of `llama-cli … | sed …` along with `sed`.
+#[cfg(not(windows))]
+fn kill(child: &mut Child) {
+    // SAFETY: kill(2) only sends a signal; the group id is the shell's pid, see `shell`
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.wait();
+}
+
+/// Lines of `pipe`, newline included, as they are written.
+fn read_lines(pipe: impl Read + Send + 'static) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line).unwrap_or(0) > 0 {
+            if sender.send(String::from_utf8_lossy(&line).into_owned()).is_err() {
+                break;
+            }
+            line.clear();
+        }
+    });
+    receiver
+}
+
+/// All of `pipe`, sent once it is closed.
+fn read_all(mut pipe: impl Read + Send + 'static) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = pipe.read_to_end(&mut bytes);
+        let _ = sender.send(String::from_utf8_lossy(&bytes).into_owned());
+    });
+    receiver
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::backend::ChatFormat;
+    use crate::test_dir::TestDir;
+
+    fn backend(command: &str, timeout_secs: u64) -> CommandBackend {
+        CommandBackend::new(CommandSettings { command: command.to_string(), timeout_secs, ..Default::default() })
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut lines = Vec::new();
+        let reply = backend("tr a-z A-Z", 10)
+            .send(&[Message::user("rate\nthis")], &mut |line| lines.push(line.to_string()), &CancelToken::default())
+            .unwrap();
+        assert_eq!(reply, "RATE\nTHIS");
+        assert_eq!(lines, vec!["RATE\n", "THIS"]);
+
+        // The conversation reaches the command in the model's chat format
+        let mut chat_ml = backend("cat", 10);
+        chat_ml.settings.chat_template.format = ChatFormat::ChatMl;
+        let reply = chat_ml.send(&[Message::user("rate this")], &mut |_| {}, &CancelToken::default()).unwrap();
+        assert_eq!(reply, "<|im_start|>user\nrate this<|im_end|>\n<|im_start|>assistant\n");
+
+        let err = backend("echo broken >&2; exit 3", 10).send(&[Message::user("")], &mut |_| {}, &CancelToken::default()).unwrap_err();
+        assert!(err.to_string().contains("broken"));
+    }
+
+    /// Whether `pid` is still running. A killed process left for init to reap counts as gone.
+    fn is_running(pid: &str) -> bool {
+        (0..20).all(|_| {
+            let output = Command::new("ps").args(["-o", "stat=", "-p", pid.trim()]).output().unwrap();
+            let stat = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            let running = !stat.is_empty() && !stat.starts_with('Z');
+            if running {
+                thread::sleep(Duration::from_millis(50));
+            }
+            running
+        })
+    }
+
+    #[test]
+    fn test_kill_pipeline() {
+        let dir = TestDir::new("command_pipeline");
+        let pid_file = dir.join("pid");
+        let command = format!("sh -c 'echo $$ > {}; exec sleep 5' | cat", pid_file.display());
+        let err = backend(&command, 1).send(&[Message::user("")], &mut |_| {}, &CancelToken::default()).unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        assert!(!is_running(&pid), "sleep {} outlived the timeout", pid);
+    }
+
+    #[test]
+    fn test_background_process() {
+        // The shell exits at once, but the process it leaves behind keeps stdout open
+        let dir = TestDir::new("command_background");
+        let pid_file = dir.join("pid");
+        let command = format!("sh -c 'echo $$ > {}; exec sleep 10' & echo early", pid_file.display());
+        let started = Instant::now();
+        let mut lines = Vec::new();
+        let reply = backend(&command, 20)
+            .send(&[Message::user("")], &mut |line| lines.push(line.to_string()), &CancelToken::default())
+            .unwrap();
+        assert_eq!(reply, "early\n");
+        assert_eq!(lines, vec!["early\n"]);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        assert!(!is_running(&pid), "sleep {} outlived the command", pid);
+    }
+
+    #[test]
+    fn test_timeout_and_cancel() {
+        let started = Instant::now();
+        let err = backend("sleep 10", 1).send(&[Message::user("")], &mut |_| {}, &CancelToken::default()).unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+
+        let cancel = CancelToken::default();
+        let token = cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            token.cancel();
+        });
+        let err = backend("sleep 10", 10).send(&[Message::user("")], &mut |_| {}, &cancel).unwrap_err();
+        assert_eq!(err.to_string(), "Cancelled");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
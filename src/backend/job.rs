@@ -0,0 +1,97 @@
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use super::{Backend, CancelToken, Message};
+
+enum Event {
+    Token(String),
+    Done(Result<String>),
+}
+
+/// A request running on a worker thread. The GUI calls `poll` every frame to pick up the
+/// reply as it streams in, without ever blocking on the backend.
+pub struct Job {
+    events: Receiver<Event>,
+    cancel: CancelToken,
+    started: Instant,
+    partial: String,
+}
+
+impl Job {
+    pub fn spawn(backend: Box<dyn Backend>, messages: Vec<Message>) -> Self {
+        let (sender, events) = mpsc::channel();
+        let cancel = CancelToken::default();
+        let token = cancel.clone();
+        thread::spawn(move || {
+            let result = backend.send(&messages, &mut |text| {
+                let _ = sender.send(Event::Token(text.to_string()));
+            }, &token);
+            let _ = sender.send(Event::Done(result));
+        });
+
+        Self {
+            events,
+            cancel,
+            started: Instant::now(),
+            partial: String::new(),
+        }
+    }
+
+    /// Take in everything the backend sent since the last call. Returns the result once the
+    /// backend is done; the job should be dropped after that.
+    pub fn poll(&mut self) -> Option<Result<String>> {
+        loop {
+            match self.events.try_recv() {
+                Ok(Event::Token(text)) => self.partial.push_str(&text),
+                Ok(Event::Done(result)) => return Some(result),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => return Some(Err(anyhow!("Backend worker stopped unexpectedly"))),
+            }
+        }
+    }
+
+    /// The reply received so far.
+    pub fn partial(&self) -> &str {
+        &self.partial
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Ask the backend to stop. The job then finishes with a "Cancelled" error.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+
+    fn wait(job: &mut Job) -> Result<String> {
+        loop {
+            if let Some(result) = job.poll() {
+                return result;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_job_streams_reply() {
+        let mut job = Job::spawn(Box::new(MockBackend::new("one two three", Duration::ZERO)), Vec::new());
+        assert_eq!(wait(&mut job).unwrap(), "one two three");
+        assert_eq!(job.partial(), "one two three");
+    }
+
+    #[test]
+    fn test_job_cancel() {
+        let mut job = Job::spawn(Box::new(MockBackend::new("one two three", Duration::from_millis(200))), Vec::new());
+        job.cancel();
+        assert_eq!(wait(&mut job).unwrap_err().to_string(), "Cancelled");
+        assert!(job.elapsed() < Duration::from_secs(1));
+    }
+}
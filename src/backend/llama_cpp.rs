@@ -0,0 +1,130 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use super::{Backend, CancelToken, ChatTemplateSettings, Message, SamplingParams};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LlamaCppSettings {
+    pub base_url: String,
+    pub sampling: SamplingParams,
+    pub timeout_secs: u64,
+    /// How the conversation is written into the prompt.
+    pub chat_template: ChatTemplateSettings,
+}
+
+impl Default for LlamaCppSettings {
+    fn default() -> Self {
+        Self {
+            base_url: "http://localhost:8080".to_string(),
+            sampling: SamplingParams::default(),
+            timeout_secs: 600,
+            chat_template: ChatTemplateSettings::default(),
+        }
+    }
+}
+
+/// Client for the llama.cpp server `/completion` protocol. The server runs a single model, so
+/// there is nothing to select; the request is sent as a raw prompt, in the chat format set up
+/// for the model.
+pub struct LlamaCppBackend {
+    settings: LlamaCppSettings,
+}
+
+impl LlamaCppBackend {
+    pub fn new(settings: LlamaCppSettings) -> Self {
+        Self { settings }
+    }
+}
+
+impl Backend for LlamaCppBackend {
+    /// The server streams server-sent events, the last one marked `stop`.
+    fn send(&self, messages: &[Message], on_token: &mut dyn FnMut(&str), cancel: &CancelToken) -> Result<String> {
+        let sampling = &self.settings.sampling;
+        // A seed past i64::MAX would wrap to a negative one, which llama.cpp reads as random
+        let seed = sampling.seed
+            .map(|seed| i64::try_from(seed).map_err(|_| anyhow!("Seed {} is too large for llama.cpp", seed)))
+            .transpose()?;
+        let body = json!({
+            "prompt": self.settings.chat_template.render(messages)?,
+            "stream": true,
+            "temperature": sampling.temperature,
+            "top_p": sampling.top_p,
+            "top_k": sampling.top_k,
+            "repeat_penalty": sampling.repeat_penalty,
+            // llama.cpp uses -1 for "until the model stops" and for a random seed
+            "n_predict": if sampling.max_tokens > 0 { i64::from(sampling.max_tokens) } else { -1 },
+            "seed": seed.unwrap_or(-1),
+        });
+
+        let client = super::http_client(self.settings.timeout_secs)?;
+        let url = format!("{}/completion", self.settings.base_url.trim_end_matches('/'));
+        super::block_on(cancel, async {
+            let response = super::send_http(client.post(&url).json(&body), &url).await?;
+            if !super::is_event_stream(&response) {
+                let json = super::parse_json(&response.text().await?)?;
+                let reply = json["content"].as_str()
+                    .ok_or_else(|| anyhow!("llama.cpp response has no content"))?;
+                cancel.check()?;
+                on_token(reply);
+                return Ok(reply.to_string());
+            }
+
+            let mut reply = String::new();
+            super::for_each_line(response, cancel, |line| {
+                let Some(data) = super::sse_data(line) else {
+                    return Ok(false);
+                };
+                let json = super::parse_json(data)?;
+                if let Some(token) = json["content"].as_str().filter(|token| !token.is_empty()) {
+                    on_token(token);
+                    reply.push_str(token);
+                }
+                Ok(json["stop"].as_bool().unwrap_or(false))
+            }).await?;
+            Ok(reply)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::test_server::serve_once;
+
+    #[test]
+    fn test_stream() {
+        let (address, server) = serve_once(
+            200,
+            "text/event-stream",
+            "data: {\"content\":\"# Exp\",\"stop\":false}\n\n\
+             data: {\"content\":\"erience\",\"stop\":false}\n\n\
+             data: {\"content\":\"\",\"stop\":true}\n\n",
+        );
+        let settings = LlamaCppSettings { base_url: format!("{}/", address), ..Default::default() };
+        let mut tokens = Vec::new();
+        let reply = LlamaCppBackend::new(settings)
+            .send(&[Message::user("rate this")], &mut |token| tokens.push(token.to_string()), &CancelToken::default())
+            .unwrap();
+        let request = server.join().unwrap();
+
+        assert_eq!(tokens, vec!["# Exp", "erience"]);
+        assert_eq!(reply, "# Experience");
+        assert!(request.starts_with("POST /completion"));
+        assert!(request.contains(r#""prompt":"rate this""#));
+        assert!(request.contains(r#""n_predict":-1"#));
+        assert!(request.contains(r#""seed":-1"#));
+    }
+
+    #[test]
+    fn test_seed_out_of_range() {
+        let settings = LlamaCppSettings {
+            sampling: SamplingParams { seed: Some(u64::MAX), ..Default::default() },
+            ..Default::default()
+        };
+        let err = LlamaCppBackend::new(settings)
+            .send(&[Message::user("rate this")], &mut |_| {}, &CancelToken::default())
+            .unwrap_err();
+        assert_eq!(err.to_string(), format!("Seed {} is too large for llama.cpp", u64::MAX));
+    }
+}
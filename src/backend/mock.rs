@@ -0,0 +1,79 @@
+use std::thread;
+use std::time::Duration;
+use anyhow::Result;
+use super::{Backend, CancelToken, Message};
+
+/// Reply of the mock backend: a well-formed assessment for the built-in rubric.
+const MOCK_REPLY: &str = "\
+Here is the assessment.
+
+# Experience
+- Familiarity with the standard library
+- Rating: Easy - Medium
+
+# Knowledge
+- Basic understanding of ownership and borrowing
+- Rating: Medium
+
+# Ambiguity
+- The expected output format is stated clearly
+- Rating: Easy
+
+# Complexity
+- A single function with a couple of edge cases
+- Rating: Medium
+
+# Overall:
+  Difficulty Medium
+";
+
+/// Deterministic stand-in for a model, for tests and demos without a server. It always gives
+/// the same reply, word by word, regardless of the request.
+pub struct MockBackend {
+    reply: String,
+    token_delay: Duration,
+}
+
+impl MockBackend {
+    pub fn new(reply: &str, token_delay: Duration) -> Self {
+        Self { reply: reply.to_string(), token_delay }
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new(MOCK_REPLY, Duration::from_millis(30))
+    }
+}
+
+impl Backend for MockBackend {
+    fn send(&self, _messages: &[Message], on_token: &mut dyn FnMut(&str), cancel: &CancelToken) -> Result<String> {
+        for token in self.reply.split_inclusive(char::is_whitespace) {
+            cancel.check()?;
+            thread::sleep(self.token_delay);
+            on_token(token);
+        }
+        Ok(self.reply.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assessment::Assessment;
+    use crate::extract::extract_assessment;
+    use crate::rubric::Rubric;
+
+    #[test]
+    fn test_reply_is_a_valid_assessment() {
+        let mock = MockBackend::new(MOCK_REPLY, Duration::ZERO);
+        let mut streamed = String::new();
+        let reply = mock.send(&[Message::user("")], &mut |token| streamed.push_str(token), &CancelToken::default()).unwrap();
+        assert_eq!(streamed, reply);
+
+        let rubric = Rubric::builtin();
+        let extraction = extract_assessment(&reply, &rubric).unwrap();
+        let assessment = Assessment::parse(&extraction.payload, &rubric).unwrap();
+        assessment.validate(&rubric).unwrap();
+    }
+}
@@ -0,0 +1,359 @@
+pub mod chat_template;
+pub mod command;
+pub mod job;
+pub mod llama_cpp;
+pub mod mock;
+pub mod ollama;
+pub mod openai;
+#[cfg(test)]
+mod test_server;
+
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::budget::ContextSettings;
+
+pub use chat_template::{ChatFormat, ChatTemplate, ChatTemplateSettings};
+pub use command::{CommandBackend, CommandSettings};
+pub use job::Job;
+pub use llama_cpp::{LlamaCppBackend, LlamaCppSettings};
+pub use mock::MockBackend;
+pub use ollama::{OllamaBackend, OllamaSettings};
+pub use openai::{OpenAiBackend, OpenAiSettings};
+
+/// Shared flag a running request checks to see whether it should stop.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Error to return once the request has been stopped.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(anyhow!("Cancelled"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+/// One turn of the conversation sent to a backend.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+impl Message {
+    pub fn system(content: &str) -> Self {
+        Self { role: Role::System, content: content.to_string() }
+    }
+
+    pub fn user(content: &str) -> Self {
+        Self { role: Role::User, content: content.to_string() }
+    }
+
+    pub fn assistant(content: &str) -> Self {
+        Self { role: Role::Assistant, content: content.to_string() }
+    }
+}
+
+/// Conversation as a single text for backends that take a raw prompt. A lone request is sent
+/// as it is; longer conversations get a heading per turn.
+pub fn flatten(messages: &[Message]) -> String {
+    if let [only] = messages {
+        return only.content.clone();
+    }
+    messages.iter()
+        .map(|message| match message.role {
+            Role::System => format!("### System:\n{}\n\n", message.content),
+            Role::User => format!("### User:\n{}\n\n", message.content),
+            Role::Assistant => format!("### Assistant:\n{}\n\n", message.content),
+        })
+        .collect::<String>() + "### Assistant:\n"
+}
+
+/// A model the request can be sent to.
+pub trait Backend: Send {
+    /// Send the conversation and return the whole reply to its last message. Parts of the
+    /// reply are passed to `on_token` as they arrive; a backend that cannot stream passes the
+    /// whole reply once. The backend checks `cancel` while it waits and gives up with an error
+    /// once it is set.
+    fn send(&self, messages: &[Message], on_token: &mut dyn FnMut(&str), cancel: &CancelToken) -> Result<String>;
+}
+
+/// Where a generated request goes. `Clipboard` is the original copy/paste workflow.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackendKind {
+    #[default]
+    Clipboard,
+    OpenAi,
+    Ollama,
+    LlamaCpp,
+    Command,
+    Mock,
+}
+
+impl BackendKind {
+    pub const ALL: [BackendKind; 6] = [
+        BackendKind::Clipboard,
+        BackendKind::OpenAi,
+        BackendKind::Ollama,
+        BackendKind::LlamaCpp,
+        BackendKind::Command,
+        BackendKind::Mock,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BackendKind::Clipboard => "Clipboard (copy/paste)",
+            BackendKind::OpenAi => "OpenAI-compatible API",
+            BackendKind::Ollama => "Ollama",
+            BackendKind::LlamaCpp => "llama.cpp server",
+            BackendKind::Command => "Local command (stdin/stdout)",
+            BackendKind::Mock => "Mock (canned demo reply)",
+        }
+    }
+}
+
+/// Sampling parameters shared by the backends. Each backend sends the ones its protocol knows.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SamplingParams {
+    pub temperature: f32,
+    pub top_p: f32,
+    pub top_k: u32,
+    pub repeat_penalty: f32,
+    /// Most tokens to generate, 0 for the server's default.
+    pub max_tokens: u32,
+    /// Fixed seed for reproducible answers, random when unset.
+    pub seed: Option<u64>,
+}
+
+impl Default for SamplingParams {
+    fn default() -> Self {
+        Self {
+            temperature: 0.7,
+            top_p: 0.9,
+            top_k: 40,
+            repeat_penalty: 1.1,
+            max_tokens: 0,
+            seed: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackendSettings {
+    pub kind: BackendKind,
+    pub openai: OpenAiSettings,
+    pub ollama: OllamaSettings,
+    pub llama_cpp: LlamaCppSettings,
+    pub command: CommandSettings,
+    pub context: ContextSettings,
+}
+
+impl BackendSettings {
+    /// Backend for the selected kind. The clipboard workflow has none: the user copies the
+    /// request and pastes the reply by hand.
+    pub fn backend(&self) -> Result<Box<dyn Backend>> {
+        Ok(match self.kind {
+            BackendKind::Clipboard => return Err(anyhow!("No backend selected, copy the prompt instead")),
+            BackendKind::OpenAi => Box::new(OpenAiBackend::new(self.openai.clone())),
+            BackendKind::Ollama => Box::new(OllamaBackend::new(self.ollama.clone())),
+            BackendKind::LlamaCpp => Box::new(LlamaCppBackend::new(self.llama_cpp.clone())),
+            BackendKind::Command => Box::new(CommandBackend::new(self.command.clone())),
+            BackendKind::Mock => Box::new(MockBackend::default()),
+        })
+    }
+
+    /// Name the context window of the selected backend is configured under: the model for the
+    /// backends that pick one, the backend otherwise.
+    pub fn model_name(&self) -> &str {
+        match self.kind {
+            BackendKind::OpenAi => &self.openai.model,
+            BackendKind::Ollama => &self.ollama.model,
+            BackendKind::Clipboard => "clipboard",
+            BackendKind::LlamaCpp => "llama.cpp",
+            BackendKind::Command => "command",
+            BackendKind::Mock => "mock",
+        }
+    }
+
+    /// Chat format of the selected backend, for the ones that take a raw prompt.
+    pub fn chat_template(&self) -> Option<&ChatTemplateSettings> {
+        match self.kind {
+            BackendKind::LlamaCpp => Some(&self.llama_cpp.chat_template),
+            BackendKind::Command => Some(&self.command.chat_template),
+            _ => None,
+        }
+    }
+}
+
+/// Backend settings file: `$QAG_BACKEND_CONFIG`, or `backend.yaml` relative to the working directory.
+pub fn settings_path() -> PathBuf {
+    env::var_os("QAG_BACKEND_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("backend.yaml"))
+}
+
+/// Settings stored in `path`, or the defaults if the file does not exist.
+pub fn load_settings(path: &Path) -> Result<BackendSettings> {
+    if !path.exists() {
+        return Ok(BackendSettings::default());
+    }
+    let yaml_str = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_yaml::from_str(&yaml_str)
+        .map_err(|e| anyhow!("Invalid backend settings {}: {}", path.display(), e))
+}
+
+pub fn save_settings(path: &Path, settings: &BackendSettings) -> Result<()> {
+    let yaml_str = serde_yaml::to_string(settings)?;
+    fs::write(path, yaml_str).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Error for a non-success HTTP status, using the server's own message when it sends one.
+fn http_error(status: reqwest::StatusCode, body: &str) -> anyhow::Error {
+    let message = serde_json::from_str::<serde_json::Value>(body).ok()
+        .and_then(|json| {
+            let error = json.get("error")?;
+            error.get("message").or(Some(error))?.as_str().map(str::to_string)
+        })
+        .unwrap_or_else(|| body.trim().to_string());
+    anyhow!("Backend returned {}: {}", status, message)
+}
+
+/// How often a waiting HTTP request checks whether it was cancelled.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// HTTP client giving up after `timeout_secs`.
+fn http_client(timeout_secs: u64) -> Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .timeout(Duration::from_secs(timeout_secs))
+        .build()?)
+}
+
+/// Run an HTTP exchange to the end, or until `cancel` is set. A cancelled exchange is dropped
+/// with its connection, so the server stops generating instead of running to the timeout.
+fn block_on<T>(cancel: &CancelToken, exchange: impl Future<Output = Result<T>>) -> Result<T> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    runtime.block_on(async {
+        let mut exchange = std::pin::pin!(exchange);
+        loop {
+            match tokio::time::timeout(CANCEL_POLL_INTERVAL, exchange.as_mut()).await {
+                Ok(result) => return result,
+                Err(_) => cancel.check()?,
+            }
+        }
+    })
+}
+
+/// Send an HTTP request and fail on a non-success status. The body is left for the caller to
+/// read, so a streamed reply can be processed as it arrives.
+async fn send_http(http_request: reqwest::RequestBuilder, url: &str) -> Result<reqwest::Response> {
+    let response = http_request.send().await
+        .with_context(|| format!("Failed to reach {}", url))?;
+    let status = response.status();
+    if !status.is_success() {
+        let text = response.text().await.unwrap_or_default();
+        return Err(http_error(status, &text));
+    }
+    Ok(response)
+}
+
+/// Whether the server answered with server-sent events rather than a single JSON document.
+fn is_event_stream(response: &reqwest::Response) -> bool {
+    response.headers().get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"))
+}
+
+/// Feed each non-empty line of a streamed body to `on_line` until it returns `true` or the body
+/// ends. Cancellation is checked between lines.
+async fn for_each_line(
+    mut response: reqwest::Response,
+    cancel: &CancelToken,
+    mut on_line: impl FnMut(&str) -> Result<bool>,
+) -> Result<()> {
+    let mut pending = Vec::new();
+    loop {
+        let chunk = response.chunk().await.context("Connection lost while reading the reply")?;
+        let end = chunk.is_none();
+        pending.extend_from_slice(&chunk.unwrap_or_default());
+        // The last line of the body may have no line break
+        while let Some(length) = pending.iter().position(|&byte| byte == b'\n').map(|i| i + 1)
+            .or_else(|| (end && !pending.is_empty()).then_some(pending.len()))
+        {
+            cancel.check()?;
+            let line: Vec<u8> = pending.drain(..length).collect();
+            let line = String::from_utf8(line).context("Invalid UTF-8 in the reply")?;
+            let line = line.trim();
+            if !line.is_empty() && on_line(line)? {
+                return cancel.check();
+            }
+        }
+        if end {
+            return cancel.check();
+        }
+    }
+}
+
+/// Marker some servers send as the last server-sent event.
+const SSE_DONE: &str = "[DONE]";
+
+/// Payload of a server-sent events `data:` line, `None` for other lines (comments, event names).
+fn sse_data(line: &str) -> Option<&str> {
+    line.strip_prefix("data:").map(str::trim)
+}
+
+fn parse_json(text: &str) -> Result<serde_json::Value> {
+    let json: serde_json::Value = serde_json::from_str(text)
+        .map_err(|e| anyhow!("Invalid response from backend: {}", e))?;
+    // Some servers report failures inside an otherwise successful stream
+    if let Some(error) = json.get("error") {
+        let message = error.get("message").unwrap_or(error);
+        return Err(anyhow!("Backend error: {}", message.as_str().map_or_else(|| message.to_string(), str::to_string)));
+    }
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flatten() {
+        assert_eq!(flatten(&[Message::user("rate this")]), "rate this");
+        assert_eq!(
+            flatten(&[Message::user("rate this"), Message::assistant("no"), Message::user("again")]),
+            "### User:\nrate this\n\n### Assistant:\nno\n\n### User:\nagain\n\n### Assistant:\n"
+        );
+        assert_eq!(
+            flatten(&[Message::system("be fair"), Message::user("rate this")]),
+            "### System:\nbe fair\n\n### User:\nrate this\n\n### Assistant:\n"
+        );
+    }
+}
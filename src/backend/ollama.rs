@@ -0,0 +1,143 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use super::{Backend, CancelToken, Message, SamplingParams};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OllamaSettings {
+    pub base_url: String,
+    pub model: String,
+    pub sampling: SamplingParams,
+    pub timeout_secs: u64,
+}
+
+impl Default for OllamaSettings {
+    fn default() -> Self {
+        Self {
+            base_url: "http://localhost:11434".to_string(),
+            model: "llama3.1".to_string(),
+            sampling: SamplingParams::default(),
+            timeout_secs: 600,
+        }
+    }
+}
+
+/// Client for the Ollama `/api/chat` protocol.
+pub struct OllamaBackend {
+    settings: OllamaSettings,
+}
+
+impl OllamaBackend {
+    pub fn new(settings: OllamaSettings) -> Self {
+        Self { settings }
+    }
+
+    /// Names of the models installed on the server, for the model picker.
+    pub fn list_models(&self) -> Result<Vec<String>> {
+        let url = self.url("/api/tags");
+        let client = super::http_client(self.settings.timeout_secs)?;
+        let json = super::block_on(&CancelToken::default(), async {
+            let response = super::send_http(client.get(&url), &url).await?;
+            super::parse_json(&response.text().await?)
+        })?;
+        let models = json["models"].as_array()
+            .ok_or_else(|| anyhow!("Ollama response has no models list"))?;
+        Ok(models.iter()
+            .filter_map(|model| model["name"].as_str().map(str::to_string))
+            .collect())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.settings.base_url.trim_end_matches('/'), path)
+    }
+}
+
+impl Backend for OllamaBackend {
+    /// Ollama streams one JSON object per line, the last one marked `done`.
+    fn send(&self, messages: &[Message], on_token: &mut dyn FnMut(&str), cancel: &CancelToken) -> Result<String> {
+        let sampling = &self.settings.sampling;
+        let mut options = json!({
+            "temperature": sampling.temperature,
+            "top_p": sampling.top_p,
+            "top_k": sampling.top_k,
+            "repeat_penalty": sampling.repeat_penalty,
+        });
+        if sampling.max_tokens > 0 {
+            options["num_predict"] = json!(sampling.max_tokens);
+        }
+        if let Some(seed) = sampling.seed {
+            options["seed"] = json!(seed);
+        }
+
+        let body = json!({
+            "model": self.settings.model,
+            "messages": messages,
+            "stream": true,
+            "options": options,
+        });
+        let url = self.url("/api/chat");
+        let client = super::http_client(self.settings.timeout_secs)?;
+        super::block_on(cancel, async {
+            let response = super::send_http(client.post(&url).json(&body), &url).await?;
+
+            let mut reply = String::new();
+            super::for_each_line(response, cancel, |line| {
+                let json = super::parse_json(line)?;
+                if let Some(token) = json["message"]["content"].as_str().filter(|token| !token.is_empty()) {
+                    on_token(token);
+                    reply.push_str(token);
+                }
+                Ok(json["done"].as_bool().unwrap_or(false))
+            }).await?;
+            Ok(reply)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::test_server::serve_once;
+
+    #[test]
+    fn test_stream() {
+        let (address, server) = serve_once(
+            200,
+            "application/x-ndjson",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"# Exp\"},\"done\":false}\n\
+             {\"message\":{\"role\":\"assistant\",\"content\":\"erience\"},\"done\":false}\n\
+             {\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true}\n",
+        );
+        let settings = OllamaSettings {
+            base_url: address,
+            sampling: SamplingParams { max_tokens: 512, seed: Some(7), ..Default::default() },
+            ..Default::default()
+        };
+        let mut tokens = Vec::new();
+        let reply = OllamaBackend::new(settings)
+            .send(&[Message::user("rate this")], &mut |token| tokens.push(token.to_string()), &CancelToken::default())
+            .unwrap();
+        let request = server.join().unwrap();
+
+        assert_eq!(tokens, vec!["# Exp", "erience"]);
+        assert_eq!(reply, "# Experience");
+        assert!(request.starts_with("POST /api/chat"));
+        assert!(request.contains(r#""stream":true"#));
+        assert!(request.contains(r#""num_predict":512"#));
+        assert!(request.contains(r#""seed":7"#));
+    }
+
+    #[test]
+    fn test_list_models() {
+        let (address, server) = serve_once(
+            200,
+            "application/json",
+            r#"{"models":[{"name":"llama3.1:latest"},{"name":"qwen2.5:7b"}]}"#,
+        );
+        let settings = OllamaSettings { base_url: address, ..Default::default() };
+        let models = OllamaBackend::new(settings).list_models().unwrap();
+        assert!(server.join().unwrap().starts_with("GET /api/tags"));
+        assert_eq!(models, vec!["llama3.1:latest", "qwen2.5:7b"]);
+    }
+}
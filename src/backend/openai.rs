@@ -0,0 +1,192 @@
+use std::env;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use super::{Backend, CancelToken, Message, SamplingParams};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OpenAiSettings {
+    /// Full chat completions URL, e.g. `http://localhost:8080/v1/chat/completions`.
+    pub endpoint: String,
+    /// Sent as a bearer token. Falls back to `$OPENAI_API_KEY` when empty.
+    pub api_key: String,
+    pub model: String,
+    pub sampling: SamplingParams,
+    pub timeout_secs: u64,
+}
+
+impl Default for OpenAiSettings {
+    fn default() -> Self {
+        Self {
+            endpoint: "https://api.openai.com/v1/chat/completions".to_string(),
+            api_key: String::new(),
+            model: "gpt-4o-mini".to_string(),
+            sampling: SamplingParams::default(),
+            timeout_secs: 300,
+        }
+    }
+}
+
+/// Client for any server speaking the OpenAI `/v1/chat/completions` protocol.
+pub struct OpenAiBackend {
+    settings: OpenAiSettings,
+}
+
+impl OpenAiBackend {
+    pub fn new(settings: OpenAiSettings) -> Self {
+        Self { settings }
+    }
+
+    fn api_key(&self) -> Option<String> {
+        if !self.settings.api_key.is_empty() {
+            return Some(self.settings.api_key.clone());
+        }
+        env::var("OPENAI_API_KEY").ok().filter(|key| !key.is_empty())
+    }
+}
+
+impl Backend for OpenAiBackend {
+    /// Send the conversation as chat messages, in order, and stream the assistant's reply to
+    /// the last one. A server that ignores `stream` and answers with one JSON document is
+    /// handled too.
+    fn send(&self, messages: &[Message], on_token: &mut dyn FnMut(&str), cancel: &CancelToken) -> Result<String> {
+        // top_k and repeat_penalty are not part of the OpenAI protocol
+        let sampling = &self.settings.sampling;
+        let mut body = json!({
+            "model": self.settings.model,
+            "messages": messages,
+            "stream": true,
+            "temperature": sampling.temperature,
+            "top_p": sampling.top_p,
+        });
+        if sampling.max_tokens > 0 {
+            body["max_tokens"] = json!(sampling.max_tokens);
+        }
+        if let Some(seed) = sampling.seed {
+            body["seed"] = json!(seed);
+        }
+        let mut http_request = super::http_client(self.settings.timeout_secs)?
+            .post(&self.settings.endpoint)
+            .json(&body);
+        if let Some(api_key) = self.api_key() {
+            http_request = http_request.bearer_auth(api_key);
+        }
+
+        super::block_on(cancel, async {
+            let response = super::send_http(http_request, &self.settings.endpoint).await?;
+            if !super::is_event_stream(&response) {
+                let json = super::parse_json(&response.text().await?)?;
+                let reply = json["choices"][0]["message"]["content"].as_str()
+                    .ok_or_else(|| anyhow!("Backend response has no choices[0].message.content"))?;
+                cancel.check()?;
+                on_token(reply);
+                return Ok(reply.to_string());
+            }
+
+            let mut reply = String::new();
+            super::for_each_line(response, cancel, |line| {
+                let Some(data) = super::sse_data(line) else {
+                    return Ok(false);
+                };
+                if data == super::SSE_DONE {
+                    return Ok(true);
+                }
+                let json = super::parse_json(data)?;
+                if let Some(token) = json["choices"][0]["delta"]["content"].as_str().filter(|token| !token.is_empty()) {
+                    on_token(token);
+                    reply.push_str(token);
+                }
+                Ok(false)
+            }).await?;
+            Ok(reply)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::backend::test_server::{serve_once, serve_silently};
+
+    fn settings(address: &str) -> OpenAiSettings {
+        OpenAiSettings {
+            endpoint: format!("{}/v1/chat/completions", address),
+            api_key: "test-key".to_string(),
+            model: "local-model".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_stream() {
+        let (address, server) = serve_once(
+            200,
+            "text/event-stream",
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{\"content\":\"Experience:\\n\"}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{\"content\":\"  Rating: Easy\"}}]}\n\n\
+             data: [DONE]\n\n",
+        );
+        let mut tokens = Vec::new();
+        let reply = OpenAiBackend::new(settings(&address))
+            .send(&[Message::user("rate this")], &mut |token| tokens.push(token.to_string()), &CancelToken::default())
+            .unwrap();
+        let request = server.join().unwrap();
+
+        assert_eq!(tokens, vec!["Experience:\n", "  Rating: Easy"]);
+        assert_eq!(reply, "Experience:\n  Rating: Easy");
+        assert!(request.contains(r#""stream":true"#));
+        assert!(request.starts_with("POST /v1/chat/completions"));
+        assert!(request.to_ascii_lowercase().contains("authorization: bearer test-key"));
+        assert!(request.contains(r#""model":"local-model""#));
+        assert!(request.contains(r#""content":"rate this""#));
+    }
+
+    #[test]
+    fn test_unstreamed_reply() {
+        let (address, server) = serve_once(
+            200,
+            "application/json",
+            r#"{"choices":[{"message":{"role":"assistant","content":"Experience:\n  Rating: Easy"}}]}"#,
+        );
+        let reply = OpenAiBackend::new(settings(&address))
+            .send(&[Message::user("rate this")], &mut |_| {}, &CancelToken::default())
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(reply, "Experience:\n  Rating: Easy");
+    }
+
+    #[test]
+    fn test_error_status() {
+        let (address, server) = serve_once(401, "application/json", r#"{"error":{"message":"bad key"}}"#);
+        let err = OpenAiBackend::new(settings(&address))
+            .send(&[Message::user("rate this")], &mut |_| {}, &CancelToken::default())
+            .unwrap_err();
+        server.join().unwrap();
+
+        assert_eq!(err.to_string(), "Backend returned 401 Unauthorized: bad key");
+    }
+
+    #[test]
+    fn test_cancel_before_reply() {
+        let (address, server) = serve_silently(Duration::from_secs(10));
+        let cancel = CancelToken::default();
+        let token = cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            token.cancel();
+        });
+        let started = Instant::now();
+        let err = OpenAiBackend::new(settings(&address))
+            .send(&[Message::user("rate this")], &mut |_| {}, &cancel)
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "Cancelled");
+        assert!(started.elapsed() < Duration::from_secs(2));
+        // The connection is closed, so the server can stop working on the request
+        assert!(server.join().unwrap() < Duration::from_secs(2));
+    }
+}
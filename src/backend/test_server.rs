@@ -0,0 +1,71 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// One-shot HTTP server for backend tests: answers the first request with `status` and `body`
+/// and hands back the raw request (headers and body) through the join handle.
+pub fn serve_once(status: u16, content_type: &str, body: &str) -> (String, JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+    let content_type = content_type.to_string();
+    let body = body.to_string();
+
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let request = read_request(&mut reader);
+
+        let response = format!(
+            "HTTP/1.1 {} Test\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status, content_type, body.len(), body
+        );
+        reader.get_mut().write_all(response.as_bytes()).unwrap();
+        request
+    });
+
+    (address, handle)
+}
+
+/// Server that reads the first request and never answers it, like a model still working
+/// through a long prompt. The join handle gives how long the client kept the connection open,
+/// up to `limit`.
+pub fn serve_silently(limit: Duration) -> (String, JoinHandle<Duration>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        read_request(&mut reader);
+
+        let started = Instant::now();
+        reader.get_ref().set_read_timeout(Some(limit)).unwrap();
+        // Returns once the client closes the connection, or fails at the limit
+        let _ = reader.read(&mut [0; 1]);
+        started.elapsed()
+    });
+
+    (address, handle)
+}
+
+/// Headers and body of one request.
+fn read_request(reader: &mut BufReader<TcpStream>) -> String {
+    let mut request = String::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+            content_length = value.trim().parse().unwrap();
+        }
+        request.push_str(&line);
+        if line == "\r\n" || line.is_empty() {
+            break;
+        }
+    }
+    let mut request_body = vec![0; content_length];
+    reader.read_exact(&mut request_body).unwrap();
+    request.push_str(&String::from_utf8_lossy(&request_body));
+    request
+}
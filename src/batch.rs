@@ -0,0 +1,381 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::assessment::Assessment;
+use crate::backend::{Backend, CancelToken, Message};
+use crate::convert::convert_reply;
+use crate::few_shot::{Example, ExampleSelection};
+use crate::prompt::PromptTemplate;
+use crate::request;
+use crate::rubric::Rubric;
+
+/// One prompt to assess, a line of a JSONL file or a row of a CSV file with a header.
+/// Only `prompt` is required; the preferences take the same names as in the GUI.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct BatchRow {
+    /// Copied to the result so it can be matched up with the source data.
+    #[serde(default)]
+    pub id: Option<String>,
+    pub prompt: String,
+    #[serde(default)]
+    pub previous_turn: Option<String>,
+    /// Name of an overall level, such as `Medium`.
+    #[serde(default)]
+    pub difficulty_pref: Option<String>,
+    /// `Short`, `Normal` or `Long`.
+    #[serde(default)]
+    pub length_pref: Option<String>,
+}
+
+/// Outcome of one row, written as one line of the JSONL output file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BatchResult {
+    /// 0-based position of the row in the input file.
+    pub row: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assessment: Option<Assessment>,
+    /// Consistency warnings and point limit violations of a valid assessment.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BatchOptions {
+    /// Requests in flight at the same time.
+    pub concurrency: usize,
+    /// Most requests started per minute, 0 for no limit.
+    pub requests_per_minute: u32,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 2,
+            requests_per_minute: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BatchProgress {
+    pub total: usize,
+    /// Rows that already had an assessment in the output file.
+    pub skipped: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+impl BatchProgress {
+    pub fn finished(&self) -> usize {
+        self.skipped + self.succeeded + self.failed
+    }
+}
+
+/// Rows of a `.csv` file, or of a JSONL file for any other extension. Blank JSONL lines are
+/// skipped.
+pub fn read_rows(path: &Path) -> Result<Vec<BatchRow>> {
+    let is_csv = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+    if is_csv {
+        let mut reader = csv::Reader::from_path(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        return reader.deserialize()
+            .enumerate()
+            .map(|(i, row)| row.with_context(|| format!("Invalid row {} of {}", i + 1, path.display())))
+            .collect();
+    }
+
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line)
+            .with_context(|| format!("Invalid line {} of {}", i + 1, path.display())))
+        .collect()
+}
+
+/// Rows that already have an assessment in `output`. Rows that failed are not included, so a
+/// resumed run retries them; the last line for a row is the one that counts.
+pub fn finished_rows(output: &Path) -> Result<HashSet<usize>> {
+    #[derive(Deserialize)]
+    struct Finished {
+        row: usize,
+        #[serde(default)]
+        error: Option<String>,
+    }
+
+    let mut finished = HashSet::new();
+    if !output.exists() {
+        return Ok(finished);
+    }
+    let text = fs::read_to_string(output)
+        .with_context(|| format!("Failed to read {}", output.display()))?;
+    // A line cut short by an interrupted run does not parse and is simply redone
+    for entry in text.lines().filter_map(|line| serde_json::from_str::<Finished>(line).ok()) {
+        if entry.error.is_none() {
+            finished.insert(entry.row);
+        } else {
+            finished.remove(&entry.row);
+        }
+    }
+    Ok(finished)
+}
+
+/// Rows to assess with one template, rubric and example library.
+pub struct Batch<'a> {
+    pub rows: &'a [BatchRow],
+    pub template: &'a PromptTemplate,
+    pub rubric: &'a Rubric,
+    pub examples: &'a [Example],
+    /// Applied per row, so each prompt gets its own most relevant examples.
+    pub selection: ExampleSelection,
+    pub options: BatchOptions,
+}
+
+impl Batch<'_> {
+    /// Assess every row that `output` does not already hold an assessment for, appending one
+    /// result line per row as soon as it is done, so an interrupted run can be resumed with the
+    /// same arguments. `make_backend` is called once per worker. Stops early when `cancel` is
+    /// set; rows in flight at that point are left for the next run.
+    pub fn run(
+        &self,
+        make_backend: &dyn Fn() -> Result<Box<dyn Backend>>,
+        output: &Path,
+        cancel: &CancelToken,
+        on_progress: &mut dyn FnMut(&BatchProgress),
+    ) -> Result<BatchProgress> {
+        let finished = finished_rows(output)?;
+        let pending: VecDeque<usize> = (0..self.rows.len()).filter(|i| !finished.contains(i)).collect();
+        let mut progress = BatchProgress {
+            total: self.rows.len(),
+            skipped: self.rows.len() - pending.len(),
+            ..Default::default()
+        };
+        on_progress(&progress);
+        if pending.is_empty() {
+            return Ok(progress);
+        }
+
+        let backends = (0..self.options.concurrency.clamp(1, pending.len()))
+            .map(|_| make_backend())
+            .collect::<Result<Vec<_>>>()?;
+        let mut file = open_output(output)?;
+        let pending = Mutex::new(pending);
+        let limiter = RateLimiter::new(self.options.requests_per_minute);
+
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            for backend in backends {
+                let sender = sender.clone();
+                let (pending, limiter) = (&pending, &limiter);
+                scope.spawn(move || {
+                    while !cancel.is_cancelled() {
+                        let Some(row) = pending.lock().unwrap().pop_front() else {
+                            break;
+                        };
+                        limiter.wait(cancel);
+                        let result = self.assess(row, backend.as_ref(), cancel);
+                        if cancel.is_cancelled() || sender.send(result).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(sender);
+
+            for result in receiver {
+                let line = serde_json::to_string(&result)?;
+                writeln!(file, "{}", line)
+                    .and_then(|_| file.flush())
+                    .with_context(|| format!("Failed to write {}", output.display()))
+                    .inspect_err(|_| cancel.cancel())?;
+                if result.error.is_some() {
+                    progress.failed += 1;
+                } else {
+                    progress.succeeded += 1;
+                }
+                on_progress(&progress);
+            }
+            Ok(progress)
+        })
+    }
+
+    fn assess(&self, row: usize, backend: &dyn Backend, cancel: &CancelToken) -> BatchResult {
+        let input = &self.rows[row];
+        let mut result = BatchResult {
+            row,
+            id: input.id.clone(),
+            assessment: None,
+            warnings: Vec::new(),
+            error: None,
+        };
+        match self.try_assess(input, backend, cancel) {
+            Ok((assessment, warnings)) => {
+                result.assessment = Some(assessment);
+                result.warnings = warnings;
+            }
+            Err(e) => result.error = Some(format!("{:#}", e)),
+        }
+        result
+    }
+
+    fn try_assess(&self, input: &BatchRow, backend: &dyn Backend, cancel: &CancelToken)
+                  -> Result<(Assessment, Vec<String>)> {
+        let length = request::length_preference(input.length_pref.as_deref().unwrap_or_default())?;
+        let previous_turn = input.previous_turn.clone().unwrap_or_default();
+        let examples = self.selection.select(self.examples, &input.prompt, &previous_turn);
+        let request = request::gen_request_content(
+            self.template, self.rubric,
+            input.prompt.clone(), previous_turn,
+            request::difficulty_preference(self.rubric, input.difficulty_pref.as_deref())?,
+            length,
+            &examples,
+            false,
+        )?;
+        let reply = backend.send(&[Message::user(&request)], &mut |_| {}, cancel)?;
+
+        let max_points = request::max_points(length);
+        let conversion = convert_reply(&reply, self.rubric, max_points)?;
+        let mut warnings = conversion.warnings;
+        warnings.extend(conversion.too_long.iter().map(|name| format!(
+            "{} has more than {} points", name, max_points.unwrap_or_default()
+        )));
+        Ok((conversion.assessment, warnings))
+    }
+}
+
+/// Open `output` for appending, ending a line cut short by an interrupted run first.
+fn open_output(output: &Path) -> Result<File> {
+    let mut file = OpenOptions::new().create(true).read(true).append(true).open(output)
+        .with_context(|| format!("Failed to open {}", output.display()))?;
+    if file.metadata()?.len() > 0 {
+        let mut last = [0; 1];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            file.write_all(b"\n")?;
+        }
+    }
+    Ok(file)
+}
+
+/// Spaces out request starts across all workers to stay under a requests-per-minute limit.
+struct RateLimiter {
+    interval: Option<Duration>,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_minute: u32) -> Self {
+        Self {
+            interval: (requests_per_minute > 0).then(|| Duration::from_secs(60) / requests_per_minute),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Block until the next request may start, or until cancelled.
+    fn wait(&self, cancel: &CancelToken) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let start = {
+            let mut next = self.next.lock().unwrap();
+            let start = (*next).max(Instant::now());
+            *next = start + interval;
+            start
+        };
+        while !cancel.is_cancelled() {
+            let remaining = start.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            thread::sleep(remaining.min(Duration::from_millis(100)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::test_dir::TestDir;
+
+    const REPLY: &str = "# Experience\n- a\n- Rating: Easy\n# Knowledge\n- b\n- Rating: Medium\n\
+                         # Ambiguity\n- c\n- Rating: Easy\n# Complexity\n- d\n- Rating: Hard\n\
+                         # Overall\n Difficulty Medium\n";
+
+    fn mock() -> Result<Box<dyn Backend>> {
+        Ok(Box::new(MockBackend::new(REPLY, Duration::ZERO)))
+    }
+
+    #[test]
+    fn test_read_rows() {
+        let dir = TestDir::new("batch_rows");
+        let csv_path = dir.join("rows.csv");
+        fs::write(&csv_path, "id,prompt,difficulty_pref,length_pref\nA,\"Write, then test\",Hard,\nB,Refactor,,long\n").unwrap();
+        let rows = read_rows(&csv_path).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].prompt, "Write, then test");
+        assert_eq!(rows[0].difficulty_pref.as_deref(), Some("Hard"));
+        assert_eq!(rows[1].length_pref.as_deref(), Some("long"));
+
+        let jsonl_path = dir.join("rows.jsonl");
+        fs::write(&jsonl_path, "{\"prompt\": \"Write\", \"previous_turn\": \"Earlier\"}\n\n{\"prompt\": \"Test\"}\n").unwrap();
+        let rows = read_rows(&jsonl_path).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].previous_turn.as_deref(), Some("Earlier"));
+        assert_eq!(rows[1].id, None);
+    }
+
+    #[test]
+    fn test_run_and_resume() {
+        let rows = vec![
+            BatchRow { id: Some("a".to_string()), prompt: "Write".to_string(), ..Default::default() },
+            BatchRow { prompt: "Test".to_string(), difficulty_pref: Some("Impossible".to_string()), ..Default::default() },
+            BatchRow { prompt: "Refactor".to_string(), ..Default::default() },
+        ];
+        let (template, rubric) = (PromptTemplate::builtin(), Rubric::builtin());
+        let batch = Batch { rows: &rows, template: &template, rubric: &rubric, examples: &[],
+                            selection: ExampleSelection::All, options: BatchOptions::default() };
+        let dir = TestDir::new("batch_run");
+        let output = dir.join("results.jsonl");
+
+        let progress = batch.run(&mock, &output, &CancelToken::default(), &mut |_| {}).unwrap();
+        assert_eq!(progress, BatchProgress { total: 3, skipped: 0, succeeded: 2, failed: 1 });
+
+        let results: Vec<BatchResult> = fs::read_to_string(&output).unwrap().lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let failed = results.iter().find(|r| r.row == 1).unwrap();
+        assert!(failed.error.as_deref().unwrap().starts_with("Unknown difficulty"));
+        let first = results.iter().find(|r| r.row == 0).unwrap();
+        assert_eq!(first.id.as_deref(), Some("a"));
+        assert_eq!(first.assessment.as_ref().unwrap().categories.len(), 4);
+
+        // An interrupted write leaves half a line behind; resuming redoes only the failed row
+        fs::OpenOptions::new().append(true).open(&output).unwrap().write_all(b"{\"row\": 2").unwrap();
+        let progress = batch.run(&mock, &output, &CancelToken::default(), &mut |_| {}).unwrap();
+        assert_eq!(progress, BatchProgress { total: 3, skipped: 2, succeeded: 0, failed: 1 });
+        assert_eq!(finished_rows(&output).unwrap(), HashSet::from([0, 2]));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let limiter = RateLimiter::new(600);
+        let started = Instant::now();
+        for _ in 0..3 {
+            limiter.wait(&CancelToken::default());
+        }
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,290 @@
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use automated_llama_text_generator::backend::{self, BackendKind, CancelToken, ChatFormat, ChatTemplateSettings, Message};
+use automated_llama_text_generator::budget::{self, RequestInput};
+use automated_llama_text_generator::batch::{self, Batch, BatchOptions};
+use automated_llama_text_generator::few_shot::{self, ExampleSelection};
+use automated_llama_text_generator::rubric;
+use automated_llama_text_generator::tokens::TokenCounter;
+use automated_llama_text_generator::{convert_reply, prompt, request, ExportFormat, PromptTemplate};
+
+/// Build assessment requests and convert LLM assessments without the GUI.
+#[derive(Parser)]
+#[command(name = "qag", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the request to send to the LLM
+    Generate {
+        /// File holding the prompt to assess, `-` for stdin
+        #[arg(long, default_value = "-")]
+        prompt: PathBuf,
+        /// File holding the previous turn answer, `-` for stdin
+        #[arg(long)]
+        previous_turn: Option<PathBuf>,
+        /// Preferred overall difficulty, one of the rubric's overall levels
+        #[arg(long)]
+        difficulty: Option<String>,
+        #[arg(long, value_enum, default_value_t = Length::Short)]
+        length: Length,
+        /// Print the short follow-up for a chat that already holds the full request
+        #[arg(long)]
+        shorten: bool,
+        /// Name of the template to use, see `$QAG_TEMPLATE_DIR`
+        #[arg(long, default_value = prompt::BUILTIN_TEMPLATE_NAME)]
+        template: String,
+        /// Use only this many enabled examples, the ones most similar to the prompt
+        #[arg(long)]
+        examples: Option<usize>,
+        /// Shrink the request to the context window configured for the backend's model
+        #[arg(long)]
+        fit: bool,
+        /// Print the tokens of each section of the request to stderr
+        #[arg(long)]
+        tokens: bool,
+        /// `tokenizer.json` to count tokens with, instead of the one in the backend settings
+        #[arg(long)]
+        tokenizer: Option<PathBuf>,
+        /// Wrap the request in a model's chat format, for a raw completion endpoint
+        #[arg(long, value_enum)]
+        chat_format: Option<ChatFormatArg>,
+        /// Wrap the request with the chat template of this `tokenizer_config.json`
+        #[arg(long, conflicts_with = "chat_format")]
+        tokenizer_config: Option<PathBuf>,
+        /// System message put before the request in the chat format
+        #[arg(long, default_value = "")]
+        system: String,
+    },
+    /// Convert a YAML or Markdown assessment, or a whole LLM reply holding one
+    Convert {
+        /// File holding the assessment, `-` for stdin
+        #[arg(default_value = "-")]
+        input: PathBuf,
+        #[arg(long, value_enum, default_value_t = Format::Markdown)]
+        to: Format,
+        /// Leave out keys and sections that are not part of the rubric
+        #[arg(long)]
+        drop_unknown: bool,
+    },
+    /// Assess every prompt of a JSONL or CSV file with the configured backend
+    Batch {
+        /// `.csv` file with a header row, or JSONL with one object per line. Columns:
+        /// `prompt`, and optionally `id`, `previous_turn`, `difficulty_pref` and `length_pref`
+        input: PathBuf,
+        /// JSONL file the results are appended to. Rows it already has an assessment for are
+        /// skipped, so an interrupted run can be resumed with the same command
+        #[arg(long)]
+        output: PathBuf,
+        /// Requests in flight at the same time
+        #[arg(long, default_value_t = BatchOptions::default().concurrency)]
+        concurrency: usize,
+        /// Most requests started per minute, 0 for no limit
+        #[arg(long, default_value_t = 0)]
+        rpm: u32,
+        /// Name of the template to use, see `$QAG_TEMPLATE_DIR`
+        #[arg(long, default_value = prompt::BUILTIN_TEMPLATE_NAME)]
+        template: String,
+        /// Use only this many enabled examples, the ones most similar to each prompt
+        #[arg(long)]
+        examples: Option<usize>,
+    },
+}
+
+/// Length preference, in the order of the GUI selector.
+#[derive(Clone, Copy, ValueEnum)]
+enum Length {
+    Short,
+    Normal,
+    Long,
+}
+
+/// Chat formats with a built-in template.
+#[derive(Clone, Copy, ValueEnum)]
+enum ChatFormatArg {
+    Llama3,
+    Inst,
+    Chatml,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Markdown,
+    Yaml,
+    Json,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let rubric = rubric::load_rubric(&rubric::rubric_path())?;
+
+    match cli.command {
+        Command::Generate {
+            prompt, previous_turn, difficulty, length, shorten, template, examples, fit, tokens, tokenizer,
+            chat_format, tokenizer_config, system,
+        } => {
+            if previous_turn.as_deref() == Some(Path::new("-")) && prompt == Path::new("-") {
+                return Err(anyhow!("Only one of --prompt and --previous-turn can be read from stdin"));
+            }
+            let template = find_template(&template)?;
+            let current_prompt = read_input(&prompt)?;
+            let previous_turn = previous_turn.map(|path| read_input(&path)).transpose()?.unwrap_or_default();
+            let library = few_shot::load_examples(&few_shot::example_dir())?;
+            let picks = example_selection(examples).pick(&library, &current_prompt, &previous_turn);
+            if examples.is_some() && !shorten {
+                for pick in &picks {
+                    eprintln!("Example {}", pick.explain());
+                }
+            }
+
+            let input = RequestInput {
+                template: &template,
+                preference_difficulty: request::difficulty_preference(&rubric, difficulty.as_deref())?,
+                rubric,
+                current_prompt,
+                previous_turn,
+                preference_length: length as usize,
+                examples: picks.into_iter().map(|pick| pick.example.clone()).collect(),
+                continuous: shorten,
+            };
+            let format = match (chat_format, &tokenizer_config) {
+                (Some(ChatFormatArg::Llama3), _) => ChatFormat::Llama3,
+                (Some(ChatFormatArg::Inst), _) => ChatFormat::Inst,
+                (Some(ChatFormatArg::Chatml), _) => ChatFormat::ChatMl,
+                (None, Some(_)) => ChatFormat::TokenizerConfig,
+                (None, None) => ChatFormat::Plain,
+            };
+            let chat_template = ChatTemplateSettings { format, tokenizer_config, system };
+            let content = if fit || tokens {
+                let settings = backend::load_settings(&backend::settings_path())?;
+                let model = settings.model_name();
+                let counter = TokenCounter::from_setting(tokenizer.as_deref().or(settings.context.tokenizer.as_deref()))?;
+                let count_tokens = |text: &str| counter.count(text);
+                let budget = settings.context.request_budget(model);
+                let conversation = budget::conversation_tokens(&[], Some(&chat_template), &count_tokens)?;
+                let fit = if fit {
+                    budget::fit_request(input, budget, conversation, &count_tokens)?
+                } else {
+                    budget::measure_request(input, budget, conversation, &count_tokens)?
+                };
+                for step in &fit.steps {
+                    eprintln!("Fit: {}", step);
+                }
+                if tokens {
+                    for (name, section_tokens) in fit.sections.rows() {
+                        eprintln!("{}: {} tokens", name, section_tokens);
+                    }
+                }
+                eprintln!("Request: {} of {} tokens for {} ({})", fit.tokens, fit.budget, model, counter.describe());
+                fit.request
+            } else {
+                input.build()?
+            };
+            let content = chat_template.render(&[Message::user(&content)])?;
+            write_output(&(content + "\n"))?;
+        }
+        Command::Convert { input, to, drop_unknown } => {
+            let conversion = convert_reply(&read_input(&input)?, &rubric, None)?;
+
+            // Notes go to stderr so the converted assessment can be piped on
+            for text in &conversion.discarded {
+                eprintln!("Discarded from the reply:\n{}", text);
+            }
+            for warning in &conversion.warnings {
+                eprintln!("Consistency: {}", warning);
+            }
+
+            let format = match to {
+                Format::Markdown => ExportFormat::Markdown,
+                Format::Yaml => ExportFormat::Yaml,
+                Format::Json => ExportFormat::Json,
+            };
+            write_output(&format.render(&conversion.assessment, !drop_unknown)?)?;
+        }
+        Command::Batch { input, output, concurrency, rpm, template, examples } => {
+            let settings = backend::load_settings(&backend::settings_path())?;
+            if settings.kind == BackendKind::Clipboard {
+                return Err(anyhow!(
+                    "Batch mode needs a backend, select one in the GUI or in {}",
+                    backend::settings_path().display()
+                ));
+            }
+            let rows = batch::read_rows(&input)?;
+            let template = find_template(&template)?;
+            let library = few_shot::load_examples(&few_shot::example_dir())?;
+            let batch = Batch {
+                rows: &rows,
+                template: &template,
+                rubric: &rubric,
+                examples: &library,
+                selection: example_selection(examples),
+                options: BatchOptions { concurrency, requests_per_minute: rpm },
+            };
+
+            let progress = batch.run(&|| settings.backend(), &output, &CancelToken::default(), &mut |progress| {
+                eprint!("\r{}/{} rows, {} failed", progress.finished(), progress.total, progress.failed);
+            })?;
+            eprintln!();
+            if progress.skipped > 0 {
+                eprintln!("{} rows were already done in {}", progress.skipped, output.display());
+            }
+            if progress.failed > 0 {
+                return Err(anyhow!("{} rows failed, run the same command again to retry them", progress.failed));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The template called `name`. Templates that failed to load are reported on stderr.
+fn find_template(name: &str) -> Result<PromptTemplate> {
+    let prompt::LoadedTemplates { templates, errors } = prompt::load_templates(&prompt::template_dir())?;
+    for error in &errors {
+        eprintln!("{:#}", error);
+    }
+    let available = templates.iter().map(|t| t.name.clone()).collect::<Vec<_>>().join(", ");
+    templates.into_iter()
+        .find(|t| t.name == name)
+        .ok_or_else(|| anyhow!("Unknown template \"{}\", available: {}", name, available))
+}
+
+/// Selection for `--examples`: the most relevant ones when a count is given, else all enabled
+/// examples of the library, see `$QAG_EXAMPLE_DIR`.
+fn example_selection(count: Option<usize>) -> ExampleSelection {
+    count.map_or(ExampleSelection::All, ExampleSelection::MostRelevant)
+}
+
+fn read_input(path: &Path) -> Result<String> {
+    if path == Path::new("-") {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text).context("Failed to read stdin")?;
+        Ok(text)
+    } else {
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))
+    }
+}
+
+/// Print `text` to stdout. A closed pipe (`qag ... | head`) is not an error.
+fn write_output(text: &str) -> Result<()> {
+    match io::stdout().lock().write_all(text.as_bytes()) {
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+        result => result.context("Failed to write output"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+    }
+}
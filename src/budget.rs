@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use crate::backend::{ChatTemplateSettings, Message};
+use crate::few_shot::Example;
+use crate::prompt::PromptTemplate;
+use crate::request;
+use crate::rubric::Rubric;
+
+/// Marker left where the previous turn was cut to fit the context.
+const TRUNCATED: &str = "\n[... truncated to fit the context ...]";
+
+/// Rough token count of `text`: a token per 4 letters or digits of a word and one per
+/// punctuation mark, which is close to what Llama-style BPE tokenizers give for English and code.
+pub fn estimate_tokens(text: &str) -> usize {
+    let mut tokens = 0;
+    let mut word_chars: usize = 0;
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            word_chars += 1;
+            continue;
+        }
+        tokens += word_chars.div_ceil(4);
+        word_chars = 0;
+        if !c.is_whitespace() {
+            tokens += 1;
+        }
+    }
+    tokens + word_chars.div_ceil(4)
+}
+
+/// Context window sizes, per model, and what to keep free of them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContextSettings {
+    /// Context window of the models not listed in `models`, in tokens.
+    pub default_tokens: usize,
+    /// Context window by model name.
+    pub models: BTreeMap<String, usize>,
+    /// Tokens left free for the reply.
+    pub reply_tokens: usize,
+    /// Shrink requests that do not fit, see `fit_request`.
+    pub auto_fit: bool,
+    /// Hugging Face `tokenizer.json` of the model, to count tokens exactly instead of
+    /// estimating them, see `tokens::TokenCounter`.
+    pub tokenizer: Option<PathBuf>,
+}
+
+impl Default for ContextSettings {
+    fn default() -> Self {
+        Self {
+            default_tokens: 8192,
+            models: BTreeMap::new(),
+            reply_tokens: 1024,
+            auto_fit: false,
+            tokenizer: None,
+        }
+    }
+}
+
+impl ContextSettings {
+    pub fn context_tokens(&self, model: &str) -> usize {
+        self.models.get(model).copied().unwrap_or(self.default_tokens)
+    }
+
+    /// Tokens the request itself may take with `model`.
+    pub fn request_budget(&self, model: &str) -> usize {
+        self.context_tokens(model).saturating_sub(self.reply_tokens)
+    }
+}
+
+/// Everything `request::gen_request_content` takes, so the request can be rebuilt with less in it.
+#[derive(Clone, Debug)]
+pub struct RequestInput<'a> {
+    pub template: &'a PromptTemplate,
+    pub rubric: Rubric,
+    pub current_prompt: String,
+    pub previous_turn: String,
+    pub preference_difficulty: usize,
+    pub preference_length: usize,
+    /// Most relevant first; the last ones are dropped first.
+    pub examples: Vec<Example>,
+    pub continuous: bool,
+}
+
+impl RequestInput<'_> {
+    pub fn build(&self) -> Result<String> {
+        self.parts().map(|parts| parts.join())
+    }
+
+    pub fn parts(&self) -> Result<request::RequestParts> {
+        request::gen_request_parts(
+            self.template, &self.rubric,
+            self.current_prompt.clone(), self.previous_turn.clone(),
+            self.preference_difficulty, self.preference_length,
+            &self.examples,
+            self.continuous,
+        )
+    }
+
+    /// Tokens in each section of the request.
+    pub fn section_tokens(&self, count_tokens: &dyn Fn(&str) -> usize) -> Result<SectionTokens> {
+        let parts = self.parts()?;
+        let prompt = count_tokens(&self.current_prompt);
+        let previous_turn = count_tokens(&self.previous_turn);
+        Ok(SectionTokens {
+            // The prompt and previous turn are filled into the template text
+            conversation: 0,
+            template: count_tokens(&parts.body).saturating_sub(prompt + previous_turn),
+            examples: count_tokens(&parts.examples),
+            prompt,
+            previous_turn,
+            preferences: count_tokens(&parts.preferences),
+        })
+    }
+}
+
+/// Tokens of `messages` as the backend receives them: rendered through `chat_template` for a
+/// raw prompt backend, message by message for a chat API.
+pub fn message_tokens(messages: &[Message], chat_template: Option<&ChatTemplateSettings>,
+                      count_tokens: &dyn Fn(&str) -> usize) -> Result<usize> {
+    match chat_template {
+        Some(chat_template) => Ok(count_tokens(&chat_template.render(messages)?)),
+        None => Ok(messages.iter().map(|message| count_tokens(&message.content)).sum()),
+    }
+}
+
+/// Tokens sent along with a request besides its own text: the `earlier` messages, and the
+/// system message and chat format tokens `chat_template` wraps the conversation in.
+pub fn conversation_tokens(earlier: &[Message], chat_template: Option<&ChatTemplateSettings>,
+                           count_tokens: &dyn Fn(&str) -> usize) -> Result<usize> {
+    let mut messages = earlier.to_vec();
+    messages.push(Message::user(""));
+    message_tokens(&messages, chat_template, count_tokens)
+}
+
+/// Tokens of each section of a request, counted apart. Tokens can merge across the joins, so
+/// the sum may differ from the request's count by a few.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SectionTokens {
+    /// What is sent with the request, see `conversation_tokens`.
+    pub conversation: usize,
+    /// The template and rubric text, without the prompt and previous turn filled into it.
+    pub template: usize,
+    pub examples: usize,
+    pub prompt: usize,
+    pub previous_turn: usize,
+    pub preferences: usize,
+}
+
+impl SectionTokens {
+    /// Name and tokens of each section, in request order.
+    pub fn rows(&self) -> [(&'static str, usize); 6] {
+        [
+            ("Conversation", self.conversation),
+            ("Template", self.template),
+            ("Examples", self.examples),
+            ("Prompt", self.prompt),
+            ("Previous turn", self.previous_turn),
+            ("Preferences", self.preferences),
+        ]
+    }
+}
+
+/// A request shrunk to fit a token budget, with what was done to it.
+#[derive(Clone, Debug)]
+pub struct Fit {
+    pub request: String,
+    /// Tokens of the request and the conversation sent with it.
+    pub tokens: usize,
+    pub budget: usize,
+    /// One line per step, in the order they were taken.
+    pub steps: Vec<String>,
+    /// Sections of the request as it was sent, after the steps.
+    pub sections: SectionTokens,
+}
+
+impl Fit {
+    pub fn fits(&self) -> bool {
+        self.tokens <= self.budget
+    }
+}
+
+/// Build the request and count its tokens, with the `conversation` tokens sent along with it,
+/// against `budget`, without changing it.
+pub fn measure_request(input: RequestInput, budget: usize, conversation: usize,
+                       count_tokens: &dyn Fn(&str) -> usize) -> Result<Fit> {
+    let request = input.build()?;
+    Ok(Fit {
+        tokens: conversation + count_tokens(&request),
+        sections: SectionTokens { conversation, ..input.section_tokens(count_tokens)? },
+        request,
+        budget,
+        steps: Vec::new(),
+    })
+}
+
+/// Build the request and, while it is over `budget` tokens as counted by `count_tokens`,
+/// drop examples from the last one, then shorten the rubric guide, then cut the end off the
+/// previous turn. The `conversation` tokens sent along with the request leave less of the budget
+/// for it. The prompt itself is never changed, so the result can still be too long.
+pub fn fit_request(mut input: RequestInput, budget: usize, conversation: usize,
+                   count_tokens: &dyn Fn(&str) -> usize) -> Result<Fit> {
+    let full_budget = budget;
+    let budget = budget.saturating_sub(conversation);
+    let mut request = input.build()?;
+    let mut tokens = count_tokens(&request);
+    let mut steps = Vec::new();
+
+    // Continuous requests carry neither the examples nor the guide
+    while tokens > budget && !input.continuous {
+        let Some(example) = input.examples.pop() else {
+            break;
+        };
+        request = input.build()?;
+        let before = std::mem::replace(&mut tokens, count_tokens(&request));
+        steps.push(format!("Dropped example \"{}\" ({})", example.title, token_change(before, tokens)));
+    }
+
+    if tokens > budget && !input.continuous {
+        input.rubric = input.rubric.compressed();
+        request = input.build()?;
+        let before = std::mem::replace(&mut tokens, count_tokens(&request));
+        steps.push(format!("Shortened the rubric guide ({})", token_change(before, tokens)));
+    }
+
+    if tokens > budget && !input.previous_turn.is_empty() {
+        // Longest start of the previous turn that fits, found by bisection over its characters
+        let previous_turn = std::mem::take(&mut input.previous_turn);
+        let boundaries: Vec<usize> = previous_turn.char_indices().map(|(i, _)| i).collect();
+        let (mut low, mut high) = (0, boundaries.len());
+        let mut best = None;
+        while low < high {
+            let middle = (low + high) / 2;
+            input.previous_turn = format!("{}{}", &previous_turn[..boundaries[middle]], TRUNCATED);
+            let candidate = input.build()?;
+            let candidate_tokens = count_tokens(&candidate);
+            if candidate_tokens <= budget {
+                best = Some((middle, candidate, candidate_tokens));
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        // Even an empty previous turn can leave the request too long; cut it anyway
+        let (kept, candidate, candidate_tokens) = match best {
+            Some(best) => best,
+            None => {
+                input.previous_turn = TRUNCATED.trim_start().to_string();
+                let candidate = input.build()?;
+                let candidate_tokens = count_tokens(&candidate);
+                (0, candidate, candidate_tokens)
+            }
+        };
+        steps.push(format!(
+            "Truncated the previous turn to {} of {} characters ({})",
+            kept, boundaries.len(), token_change(tokens, candidate_tokens)
+        ));
+        request = candidate;
+        tokens = candidate_tokens;
+    }
+
+    if tokens > budget {
+        steps.push(format!("Still {} tokens over the budget: shorten the prompt itself", tokens - budget));
+    }
+    let sections = SectionTokens { conversation, ..input.section_tokens(count_tokens)? };
+    Ok(Fit { request, tokens: conversation + tokens, budget: full_budget, steps, sections })
+}
+
+/// What a fitting step did to the request's size, e.g. "-120 tokens".
+fn token_change(before: usize, after: usize) -> String {
+    format!("{:+} tokens", after as i64 - before as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::ChatFormat;
+    use crate::few_shot;
+
+    fn input<'a>(template: &'a PromptTemplate, previous_turn: &str) -> RequestInput<'a> {
+        RequestInput {
+            template,
+            rubric: Rubric::builtin(),
+            current_prompt: "Write a function that merges two sorted lists".to_string(),
+            previous_turn: previous_turn.to_string(),
+            preference_difficulty: 0,
+            preference_length: 0,
+            examples: few_shot::builtin_examples(),
+            continuous: false,
+        }
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("Hello, world!"), 6);
+        assert_eq!(estimate_tokens("fn main() {}"), 6);
+    }
+
+    #[test]
+    fn test_context_settings() {
+        let mut settings = ContextSettings::default();
+        settings.models.insert("llama3.1".to_string(), 4096);
+        assert_eq!(settings.request_budget("llama3.1"), 3072);
+        assert_eq!(settings.request_budget("other"), 7168);
+    }
+
+    #[test]
+    fn test_section_tokens() {
+        let template = PromptTemplate::builtin();
+        let input = input(&template, "Here is the code you asked for.");
+        let fit = measure_request(input.clone(), 8192, 0, &estimate_tokens).unwrap();
+        assert_eq!(fit.request, input.build().unwrap());
+        let sections = fit.sections;
+        assert_eq!(sections.prompt, estimate_tokens(&input.current_prompt));
+        assert_eq!(sections.previous_turn, 9);
+        assert_eq!(sections.examples, estimate_tokens(&few_shot::render_examples(&input.examples)));
+        let sum: usize = sections.rows().iter().map(|(_, tokens)| tokens).sum();
+        assert!(sum.abs_diff(fit.tokens) <= 5, "{} sections vs {} request", sum, fit.tokens);
+    }
+
+    #[test]
+    fn test_conversation_tokens() {
+        let earlier = [Message::user("rate this"), Message::assistant("# Experience")];
+        assert_eq!(conversation_tokens(&[], None, &estimate_tokens).unwrap(), 0);
+        assert_eq!(conversation_tokens(&earlier, None, &estimate_tokens).unwrap(), 6);
+        let chat_ml = ChatTemplateSettings { format: ChatFormat::ChatMl, ..Default::default() };
+        assert!(conversation_tokens(&earlier, Some(&chat_ml), &estimate_tokens).unwrap() > 6);
+        assert!(conversation_tokens(&[], Some(&chat_ml), &estimate_tokens).unwrap() > 0);
+
+        // What is sent with the request leaves less room for it
+        let template = PromptTemplate::builtin();
+        let full = input(&template, "").build().unwrap();
+        let budget = estimate_tokens(&full);
+        let fit = fit_request(input(&template, ""), budget, 50, &estimate_tokens).unwrap();
+        assert!(fit.fits());
+        assert!(fit.steps[0].starts_with("Dropped example "));
+        assert_eq!(fit.sections.conversation, 50);
+        assert_eq!(fit.budget, budget);
+        assert!(!measure_request(input(&template, ""), budget, 50, &estimate_tokens).unwrap().fits());
+    }
+
+    #[test]
+    fn test_fit_request() {
+        let template = PromptTemplate::builtin();
+        let full = input(&template, "").build().unwrap();
+        let fit = fit_request(input(&template, ""), estimate_tokens(&full), 0, &estimate_tokens).unwrap();
+        assert!(fit.steps.is_empty());
+        assert_eq!(fit.request, full);
+        assert_eq!(fit.sections.previous_turn, 0);
+        assert!(fit.sections.examples > fit.sections.template);
+
+        // Without examples the request still needs the rubric shortened and half the previous turn
+        let previous_turn = "Here is the code you asked for. ".repeat(200);
+        let mut bare = input(&template, &previous_turn[..previous_turn.len() / 2]);
+        bare.examples.clear();
+        bare.rubric = bare.rubric.compressed();
+        let budget = estimate_tokens(&bare.build().unwrap());
+
+        let fit = fit_request(input(&template, &previous_turn), budget, 0, &estimate_tokens).unwrap();
+        assert!(fit.fits());
+        assert_eq!(fit.steps.len(), 4);
+        assert!(fit.steps[0].starts_with("Dropped example \"Debugging a Telnet connection error\""));
+        assert!(fit.steps[2].starts_with("Shortened the rubric guide"));
+        assert!(fit.steps[3].starts_with("Truncated the previous turn to "));
+        assert!(fit.request.contains(TRUNCATED));
+
+        assert!(fit.sections.previous_turn < estimate_tokens(&previous_turn));
+        assert_eq!(fit.sections.examples, 0);
+
+        let fit = fit_request(input(&template, ""), 100, 0, &estimate_tokens).unwrap();
+        assert!(!fit.fits());
+        assert!(fit.steps.last().unwrap().starts_with("Still "));
+    }
+
+    #[test]
+    fn test_fit_request_recounts() {
+        // A count that goes up when a step takes something out must not be taken for a saving
+        let count = |text: &str| estimate_tokens(text) + if text.contains("Example 2:") { 0 } else { 10_000 };
+        let template = PromptTemplate::builtin();
+        let budget = count(&input(&template, "").build().unwrap()) - 1;
+        let fit = fit_request(input(&template, ""), budget, 0, &count).unwrap();
+        assert_eq!(fit.tokens, count(&fit.request));
+        assert!(!fit.fits());
+        assert!(fit.steps[0].contains("(+"), "{}", fit.steps[0]);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::assessment::{Assessment, OVERALL_KEY};
+use crate::rubric::Rubric;
+
+/// How many steps of the rating scale the overall difficulty may sit away from the average of
+/// the category ratings before it is reported.
+const MAX_OVERALL_DISTANCE: f32 = 1.5;
+
+/// Soft checks on a parsed assessment, mirroring what the request asks of the LLM. These are
+/// warnings only: the assessment is still usable, it just may not read as a fair judgement.
+pub fn check_consistency(assessment: &Assessment, rubric: &Rubric) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    let rated: Vec<_> = assessment.categories.iter()
+        .filter(|c| rubric.category_names().contains(&c.name.as_str()))
+        .collect();
+
+    if let [first, rest @ ..] = rated.as_slice() {
+        if !rest.is_empty() && rest.iter().all(|c| c.rating == first.rating) {
+            warnings.push(format!(
+                "All {} category ratings are \"{}\", which does not read like a subjective judgement",
+                rated.len(), first.rating
+            ));
+        }
+    }
+
+    // Only comparable when the overall level is a step of the rating scale as well
+    let overall = assessment.overall.as_deref()
+        .and_then(|overall| Some((overall, rubric.rating_step(overall)?)));
+    let steps: Option<Vec<usize>> = rated.iter().map(|c| rubric.rating_step(&c.rating)).collect();
+    if let (Some((overall, overall_step)), Some(steps)) = (overall, steps.filter(|steps| !steps.is_empty())) {
+        let average = steps.iter().sum::<usize>() as f32 / steps.len() as f32;
+        if (overall_step as f32 - average).abs() > MAX_OVERALL_DISTANCE {
+            warnings.push(format!(
+                "{} difficulty \"{}\" is far from the category ratings (average {:.1} steps from {})",
+                OVERALL_KEY, overall, average, rubric.ratings[0]
+            ));
+        }
+    }
+
+    for category in &assessment.categories {
+        if category.notes.is_empty() {
+            warnings.push(format!("{} has no notes explaining its rating", category.name));
+        }
+    }
+
+    warnings
+}
+
+/// Names of the categories with more notes than `max_points`.
+pub fn check_length(assessment: &Assessment, max_points: usize) -> Vec<&str> {
+    assessment.categories.iter()
+        .filter(|c| c.notes.len() > max_points)
+        .map(|c| c.name.as_str())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assessment::Category;
+
+    fn assessment(ratings: [&str; 4], overall: &str) -> Assessment {
+        Assessment {
+            categories: Rubric::builtin().category_names().into_iter()
+                .zip(ratings)
+                .map(|(name, rating)| Category {
+                    name: name.to_string(),
+                    notes: vec!["note".to_string()],
+                    rating: rating.to_string(),
+                    extra: Default::default(),
+                })
+                .collect(),
+            overall: Some(overall.to_string()),
+            extra_sections: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_consistent_assessment() {
+        let rubric = Rubric::builtin();
+        let ok = assessment(["Medium", "Medium - Hard", "Medium", "Hard"], "Medium");
+        assert!(check_consistency(&ok, &rubric).is_empty());
+    }
+
+    #[test]
+    fn test_inconsistent_assessment() {
+        let rubric = Rubric::builtin();
+        let mut same = assessment(["Easy"; 4], "Hard");
+        same.categories[1].notes.clear();
+        let warnings = check_consistency(&same, &rubric);
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[0].starts_with("All 4 category ratings are \"Easy\""));
+        assert!(warnings[1].starts_with("Overall difficulty \"Hard\" is far"));
+        assert_eq!(warnings[2], "Knowledge has no notes explaining its rating");
+    }
+
+    #[test]
+    fn test_length_limit() {
+        let mut long = assessment(["Easy", "Medium", "Medium", "Hard"], "Medium");
+        long.categories[2].notes = vec!["point".to_string(); 4];
+        assert_eq!(check_length(&long, 3), vec!["Ambiguity"]);
+        assert!(check_length(&long, 5).is_empty());
+    }
+}
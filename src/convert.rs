@@ -0,0 +1,105 @@
+use anyhow::Result;
+use crate::assessment::Assessment;
+use crate::check;
+use crate::extract::extract_assessment;
+use crate::rubric::Rubric;
+
+/// Output format of a converted assessment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// The Markdown answer format, the one that gets pasted back into the chat.
+    Markdown,
+    /// The YAML answer format, which parses back with `Assessment::from_yaml`.
+    Yaml,
+    /// The parsed `Assessment` structure, for other tools.
+    Json,
+}
+
+impl ExportFormat {
+    /// Render `assessment`. With `keep_unknown`, keys and sections outside the rubric are kept.
+    pub fn render(&self, assessment: &Assessment, keep_unknown: bool) -> Result<String> {
+        Ok(match self {
+            ExportFormat::Markdown => assessment.to_markdown(keep_unknown),
+            ExportFormat::Yaml => assessment.to_yaml(keep_unknown)?,
+            ExportFormat::Json => serde_json::to_string_pretty(assessment)? + "\n",
+        })
+    }
+}
+
+/// A reply turned into a validated assessment, with everything worth telling the user about it.
+#[derive(Clone, Debug)]
+pub struct Conversion {
+    pub assessment: Assessment,
+    /// Chatter and fences cut from around the assessment.
+    pub discarded: Vec<String>,
+    /// Keys and sections that are not part of the rubric, see `Assessment::unknown_keys`.
+    pub unknown_keys: Vec<String>,
+    /// Consistency warnings, see `check::check_consistency`.
+    pub warnings: Vec<String>,
+    /// Categories with more than `max_points` notes.
+    pub too_long: Vec<String>,
+}
+
+/// Find the assessment in an LLM reply (or take a bare YAML/Markdown assessment), parse it and
+/// validate it against the rubric. The soft checks are run as well; their findings are
+/// returned rather than treated as errors. `max_points` is the point limit of the request,
+/// `None` for no limit.
+pub fn convert_reply(reply: &str, rubric: &Rubric, max_points: Option<usize>) -> Result<Conversion> {
+    let extraction = extract_assessment(reply, rubric)?;
+    let assessment = Assessment::parse(&extraction.payload, rubric)?;
+    assessment.validate(rubric)?;
+
+    let too_long = match max_points {
+        Some(max_points) => check::check_length(&assessment, max_points).into_iter().map(str::to_string).collect(),
+        None => Vec::new(),
+    };
+    Ok(Conversion {
+        unknown_keys: assessment.unknown_keys(),
+        warnings: check::check_consistency(&assessment, rubric),
+        discarded: extraction.discarded,
+        too_long,
+        assessment,
+    })
+}
+
+/// Convert a YAML or Markdown assessment (or a reply holding one) to the Markdown answer format.
+pub fn yaml_to_markdown(text: &str, rubric: &Rubric, keep_unknown: bool) -> Result<String> {
+    let conversion = convert_reply(text, rubric, None)?;
+    ExportFormat::Markdown.render(&conversion.assessment, keep_unknown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLY: &str = "Here you go:\n```yaml\n\
+        Experience:\n  Note: [a, b, c]\n  Rating: Easy\n\
+        Knowledge:\n  Note: [d]\n  Rating: Medium\n\
+        Ambiguity:\n  Note: [e]\n  Rating: Easy\n\
+        Complexity:\n  Note: [f]\n  Rating: Hard\n\
+        Summary: short\n\
+        Overall: Medium\n```";
+
+    #[test]
+    fn test_convert_reply() {
+        let rubric = Rubric::builtin();
+        let conversion = convert_reply(REPLY, &rubric, Some(2)).unwrap();
+        assert_eq!(conversion.discarded, vec!["Here you go:", "```yaml", "```"]);
+        assert_eq!(conversion.unknown_keys, vec!["Summary"]);
+        assert!(conversion.warnings.is_empty());
+        assert_eq!(conversion.too_long, vec!["Experience"]);
+
+        let markdown = yaml_to_markdown(REPLY, &rubric, false).unwrap();
+        assert!(markdown.starts_with("# Experience\n- a\n- b\n- c\n- Rating: Easy\n"));
+        assert!(!markdown.contains("Summary"));
+        assert!(ExportFormat::Json.render(&conversion.assessment, true).unwrap().contains("\"rating\": \"Hard\""));
+    }
+
+    #[test]
+    fn test_convert_invalid_reply() {
+        let rubric = Rubric::builtin();
+        let reply = REPLY.replace("Overall: Medium", "Overall: Very Hard");
+        let err = convert_reply(&reply, &rubric, None).unwrap_err();
+        assert!(err.to_string().starts_with("Ratings outside the rubric scale"));
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{anyhow, Result};
+use crate::assessment::{split_heading, AssessmentFormat, OVERALL_KEY};
+use crate::rubric::Rubric;
+
+/// Assessment found inside a chat reply, with everything that was cut around it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Extraction {
+    pub payload: String,
+    pub format: AssessmentFormat,
+    pub discarded: Vec<String>,
+}
+
+/// Find the assessment inside an arbitrary LLM reply. A fenced block (```yaml, ```markdown,
+/// plain ```) that mentions a rubric section wins; otherwise the assessment is the run of lines
+/// from the first rubric section heading to the last line that still looks like part of it.
+pub fn extract_assessment(reply: &str, rubric: &Rubric) -> Result<Extraction> {
+    let lines: Vec<&str> = reply.lines().collect();
+
+    let (start, end, fenced) = match fenced_block(&lines, rubric) {
+        Some((start, end)) => (start, end, true),
+        None => {
+            let start = lines.iter()
+                .position(|line| is_section_heading(line, rubric))
+                .ok_or_else(|| anyhow!(
+                    "No assessment found: the reply mentions none of {}",
+                    rubric.category_names().join(", ")
+                ))?;
+            let end = lines[start..].iter()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .take_while(|(_, line)| is_assessment_line(line, rubric))
+                .last()
+                .map(|(i, _)| start + i + 1)
+                .unwrap_or(start + 1);
+            (start, end, false)
+        }
+    };
+
+    let payload = lines[start..end].join("\n");
+    let mut discarded = Vec::new();
+    // The fence lines themselves sit just outside the payload
+    let (before_end, after_start) = if fenced { (start - 1, end + 1) } else { (start, end) };
+    push_discarded(&mut discarded, &lines[..before_end]);
+    if fenced {
+        push_discarded(&mut discarded, &lines[before_end..start]);
+        push_discarded(&mut discarded, &lines[end..after_start.min(lines.len())]);
+    }
+    push_discarded(&mut discarded, &lines[after_start.min(lines.len())..]);
+
+    Ok(Extraction {
+        format: AssessmentFormat::detect(&payload),
+        payload,
+        discarded,
+    })
+}
+
+/// Line range (exclusive of the fences) of the first fenced block that holds a rubric section.
+fn fenced_block(lines: &[&str], rubric: &Rubric) -> Option<(usize, usize)> {
+    let mut open: Option<usize> = None;
+    for (i, line) in lines.iter().enumerate() {
+        if !line.trim_start().starts_with("```") {
+            continue;
+        }
+        match open {
+            None => open = Some(i),
+            Some(fence) => {
+                let body = &lines[fence + 1..i];
+                if body.iter().any(|line| is_section_heading(line, rubric)) {
+                    return Some((fence + 1, i));
+                }
+                open = None;
+            }
+        }
+    }
+    None
+}
+
+fn push_discarded(discarded: &mut Vec<String>, lines: &[&str]) {
+    let text = lines.join("\n");
+    let text = text.trim();
+    if !text.is_empty() {
+        discarded.push(text.to_string());
+    }
+}
+
+fn is_section_heading(line: &str, rubric: &Rubric) -> bool {
+    let (title, _) = split_heading(line);
+    rubric.category_names().into_iter()
+        .chain([OVERALL_KEY])
+        .any(|name| name.eq_ignore_ascii_case(title))
+}
+
+/// Lines that can appear inside an assessment: headings, bullets, indented YAML and the
+/// `Difficulty ...` line under Overall.
+fn is_assessment_line(line: &str, rubric: &Rubric) -> bool {
+    let trimmed = line.trim_start();
+    is_section_heading(line, rubric)
+        || trimmed.starts_with('#')
+        || trimmed.starts_with(['-', '*', '+'])
+        || line.starts_with([' ', '\t'])
+        || trimmed.to_ascii_lowercase().starts_with("difficulty")
+        || trimmed.to_ascii_lowercase().starts_with("rating:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fenced_yaml_with_chatter() {
+        let reply = "Here is your assessment:\n\n```yaml\nExperience:\n  Rating: Easy\nOverall: Easy\n```\n\nLet me know if you need changes!";
+        let extraction = extract_assessment(reply, &Rubric::builtin()).unwrap();
+        assert_eq!(extraction.payload, "Experience:\n  Rating: Easy\nOverall: Easy");
+        assert_eq!(extraction.format, AssessmentFormat::Yaml);
+        assert_eq!(extraction.discarded, vec![
+            "Here is your assessment:",
+            "```yaml",
+            "```",
+            "Let me know if you need changes!",
+        ]);
+    }
+
+    #[test]
+    fn test_unfenced_markdown_with_chatter() {
+        let reply = "Sure! Below is the rating.\n# Experience\n- Rust\n- Rating: Easy\n\nOverall:\n  Difficulty Easy\nHope this helps.";
+        let extraction = extract_assessment(reply, &Rubric::builtin()).unwrap();
+        assert_eq!(extraction.payload, "# Experience\n- Rust\n- Rating: Easy\n\nOverall:\n  Difficulty Easy");
+        assert_eq!(extraction.format, AssessmentFormat::Markdown);
+        assert_eq!(extraction.discarded, vec!["Sure! Below is the rating.", "Hope this helps."]);
+    }
+
+    #[test]
+    fn test_no_assessment() {
+        assert!(extract_assessment("I cannot help with that.", &Rubric::builtin()).is_err());
+    }
+}
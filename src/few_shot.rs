@@ -0,0 +1,328 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::assessment::Assessment;
+use crate::retrieval::Bm25;
+use crate::rubric::Rubric;
+
+/// Examples shipped with the tool, the same as the repository's `few_shot` directory.
+const BUILTIN_EXAMPLES: [(&str, &str); 2] = [
+    ("sysinfo-comments", include_str!("../few_shot/sysinfo-comments.yaml")),
+    ("telnet-debugging", include_str!("../few_shot/telnet-debugging.yaml")),
+];
+
+const EXTENSION: &str = "yaml";
+
+/// A prompt with the assessment expected for it, shown to the LLM in the full request.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Example {
+    /// File name in the example directory, without the extension. Not stored in the file.
+    #[serde(skip)]
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Whether the example goes into the full request.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub previous_answer: String,
+    pub prompt: String,
+    /// Expected assessment, in the Markdown format the request asks for.
+    pub assessment: String,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Example {
+    pub fn from_yaml(id: &str, yaml_str: &str) -> Result<Self> {
+        let mut example: Example = serde_yaml::from_str(yaml_str)
+            .map_err(|e| anyhow!("Invalid example \"{}\": {}", id, e))?;
+        example.id = id.to_string();
+        Ok(example)
+    }
+
+    /// Check that the example can be shown to the LLM: a title, a prompt and an assessment
+    /// that is valid for `rubric`.
+    pub fn validate(&self, rubric: &Rubric) -> Result<()> {
+        if self.title.trim().is_empty() {
+            return Err(anyhow!("The example needs a title"));
+        }
+        if self.prompt.trim().is_empty() {
+            return Err(anyhow!("The example needs a prompt"));
+        }
+        Assessment::parse(&self.assessment, rubric)
+            .and_then(|assessment| assessment.validate(rubric))
+            .with_context(|| format!("Example \"{}\" has an invalid assessment", self.title))
+    }
+}
+
+/// How the examples of a full request are picked among the enabled ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExampleSelection {
+    /// Every enabled example.
+    #[default]
+    All,
+    /// The given number of enabled examples most similar to the prompt and previous turn.
+    MostRelevant(usize),
+}
+
+/// Why an example was picked for the request.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PickReason {
+    /// Enabled, and every enabled example is used.
+    Enabled,
+    /// Shares these words with the prompt or previous turn, the strongest first.
+    Similar { score: f64, terms: Vec<String> },
+    /// Nothing else was similar enough; taken in library order to fill the count.
+    Filler,
+}
+
+#[derive(Clone, Debug)]
+pub struct Pick<'a> {
+    pub example: &'a Example,
+    pub reason: PickReason,
+}
+
+impl Pick<'_> {
+    /// One line for the prompt preview.
+    pub fn explain(&self) -> String {
+        match &self.reason {
+            PickReason::Enabled => format!("{}: enabled", self.example.title),
+            PickReason::Similar { score, terms } => format!(
+                "{}: score {:.2}, shared words: {}",
+                self.example.title, score, terms.iter().take(6).cloned().collect::<Vec<_>>().join(", ")
+            ),
+            PickReason::Filler => format!("{}: no similar example left, taken in library order", self.example.title),
+        }
+    }
+}
+
+impl ExampleSelection {
+    /// Pick the examples for a request among the enabled `examples`. Similarity is BM25 over
+    /// each example's title, tags, prompt and previous answer, computed locally.
+    pub fn pick<'a>(&self, examples: &'a [Example], current_prompt: &str, previous_turn: &str) -> Vec<Pick<'a>> {
+        let enabled: Vec<&Example> = examples.iter().filter(|example| example.enabled).collect();
+        let count = match *self {
+            ExampleSelection::All => {
+                return enabled.into_iter()
+                    .map(|example| Pick { example, reason: PickReason::Enabled })
+                    .collect();
+            }
+            ExampleSelection::MostRelevant(count) => count,
+        };
+
+        let documents: Vec<String> = enabled.iter()
+            .map(|example| format!(
+                "{}\n{}\n{}\n{}", example.title, example.tags.join(" "), example.prompt, example.previous_answer
+            ))
+            .collect();
+        let query = format!("{}\n{}", current_prompt, previous_turn);
+        let mut picks: Vec<Pick> = Bm25::new(&documents).search(&query, count)
+            .into_iter()
+            .map(|hit| Pick {
+                example: enabled[hit.index],
+                reason: PickReason::Similar { score: hit.score, terms: hit.terms },
+            })
+            .collect();
+
+        // A request with fewer examples than asked for would lose the format demonstration
+        for example in enabled {
+            if picks.len() >= count {
+                break;
+            }
+            if !picks.iter().any(|pick| std::ptr::eq(pick.example, example)) {
+                picks.push(Pick { example, reason: PickReason::Filler });
+            }
+        }
+        picks
+    }
+
+    /// The picked examples, as `request::gen_request_content` takes them.
+    pub fn select(&self, examples: &[Example], current_prompt: &str, previous_turn: &str) -> Vec<Example> {
+        self.pick(examples, current_prompt, previous_turn)
+            .into_iter()
+            .map(|pick| pick.example.clone())
+            .collect()
+    }
+}
+
+pub fn builtin_examples() -> Vec<Example> {
+    BUILTIN_EXAMPLES.iter()
+        .map(|(id, yaml_str)| Example::from_yaml(id, yaml_str).expect("built-in examples must be valid"))
+        .collect()
+}
+
+/// Directory the example library is kept in: `$QAG_EXAMPLE_DIR`, or `example_library` relative
+/// to the working directory. Not the repository's `few_shot` directory, which holds the built-in
+/// examples and must not be rewritten by editing the library.
+pub fn example_dir() -> PathBuf {
+    env::var_os("QAG_EXAMPLE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("example_library"))
+}
+
+/// Every `.yaml` example in `dir`, sorted by id. Until the directory exists the library is the
+/// built-in examples; it is created with them on the first change.
+pub fn load_examples(dir: &Path) -> Result<Vec<Example>> {
+    if !dir.is_dir() {
+        return Ok(builtin_examples());
+    }
+
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == EXTENSION) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    paths.iter()
+        .map(|path| {
+            let id = path.file_stem().and_then(|stem| stem.to_str())
+                .ok_or_else(|| anyhow!("Invalid example file name: {}", path.display()))?;
+            let yaml_str = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            Example::from_yaml(id, &yaml_str)
+        })
+        .collect()
+}
+
+/// Write `example` to `dir`, replacing the file with the same id.
+pub fn save_example(dir: &Path, example: &Example) -> Result<()> {
+    create_library(dir)?;
+    write_example(dir, example)
+}
+
+pub fn delete_example(dir: &Path, id: &str) -> Result<()> {
+    create_library(dir)?;
+    let path = example_path(dir, id);
+    fs::remove_file(&path).with_context(|| format!("Failed to delete {}", path.display()))
+}
+
+/// Id for a new example: its title in lowercase with dashes, made unique among `examples`.
+pub fn new_id(title: &str, examples: &[Example]) -> String {
+    let slug = title.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-");
+    let slug = if slug.is_empty() { "example".to_string() } else { slug };
+
+    let taken = |id: &str| examples.iter().any(|example| example.id == id);
+    if !taken(&slug) {
+        return slug;
+    }
+    (2..).map(|n| format!("{}-{}", slug, n))
+        .find(|id| !taken(id))
+        .expect("some numbered id is free")
+}
+
+/// The examples as they are appended to the full request.
+pub fn render_examples(examples: &[Example]) -> String {
+    examples.iter()
+        .enumerate()
+        .map(|(i, example)| format!(
+            "\n---\nExample {}:\nPrevious Answer:\n{}\nCurrent Prompt:\n{}\nExpected Markdown:\n{}\n---\n",
+            i + 1,
+            if example.previous_answer.trim().is_empty() { "(None)" } else { example.previous_answer.trim_end() },
+            example.prompt.trim_end(),
+            example.assessment.trim_end(),
+        ))
+        .collect()
+}
+
+/// Create `dir` holding the built-in examples, which were the library while it did not exist.
+fn create_library(dir: &Path) -> Result<()> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    for example in builtin_examples() {
+        write_example(dir, &example)?;
+    }
+    Ok(())
+}
+
+fn write_example(dir: &Path, example: &Example) -> Result<()> {
+    let path = example_path(dir, &example.id);
+    let yaml_str = serde_yaml::to_string(example)?;
+    fs::write(&path, yaml_str).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn example_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.{}", id, EXTENSION))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_builtin_examples() {
+        let rubric = Rubric::builtin();
+        let examples = builtin_examples();
+        assert_eq!(examples.len(), 2);
+        for example in &examples {
+            example.validate(&rubric).unwrap();
+        }
+
+        let text = render_examples(&examples);
+        assert!(text.contains("Example 2:\nPrevious Answer:\n"));
+        assert!(text.contains("Previous Answer:\n(None)\n"));
+        assert!(text.contains("Resource temporarily unavailable (os error 35)"));
+    }
+
+    #[test]
+    fn test_selection() {
+        let mut examples = builtin_examples();
+        let mut python = examples[0].clone();
+        python.title = "Sorting in Python".to_string();
+        python.tags = vec!["python".to_string()];
+        python.prompt = "Sort a list of dictionaries by two keys".to_string();
+        examples.push(python);
+
+        let all = ExampleSelection::All.pick(&examples, "anything", "");
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].reason, PickReason::Enabled);
+
+        let picks = ExampleSelection::MostRelevant(2).pick(&examples, "Why does my Python sort ignore the second key?", "");
+        assert_eq!(picks[0].example.title, "Sorting in Python");
+        assert!(picks[0].explain().starts_with("Sorting in Python: score "));
+        assert!(matches!(&picks[0].reason, PickReason::Similar { terms, .. } if terms.contains(&"python".to_string())));
+        assert_eq!(picks.len(), 2);
+
+        examples[2].enabled = false;
+        let picks = ExampleSelection::MostRelevant(1).pick(&examples, "Why does my Python sort ignore the second key?", "");
+        assert_ne!(picks[0].example.title, "Sorting in Python");
+        assert_eq!(ExampleSelection::MostRelevant(5).select(&examples, "", "").len(), 2);
+    }
+
+    #[test]
+    fn test_library() {
+        let tmp = TestDir::new("few_shot");
+        let dir = tmp.join("few_shot");
+        assert_eq!(load_examples(&dir).unwrap(), builtin_examples());
+
+        // The first change writes out the built-in examples next to the new one
+        let mut example = builtin_examples().remove(0);
+        example.title = "Sorting in Python".to_string();
+        example.id = new_id(&example.title, &builtin_examples());
+        example.enabled = false;
+        save_example(&dir, &example).unwrap();
+        let examples = load_examples(&dir).unwrap();
+        assert_eq!(examples.len(), 3);
+        assert_eq!(examples[0], example);
+        assert_eq!(new_id("Sorting in Python!", &examples), "sorting-in-python-2");
+
+        delete_example(&dir, "telnet-debugging").unwrap();
+        let ids: Vec<_> = load_examples(&dir).unwrap().into_iter().map(|example| example.id).collect();
+        assert_eq!(ids, vec!["sorting-in-python", "sysinfo-comments"]);
+    }
+}
@@ -0,0 +1,75 @@
+//! Request building and answer conversion for LLM-assisted prompt assessments.
+//!
+//! The assessment rates a prompt on the categories of a [`Rubric`]. The flow is:
+//!
+//! 1. [`gen_request_content`] renders a [`PromptTemplate`] with the rubric, the prompt to
+//!    assess and the user's preferences into the request sent to the LLM.
+//! 2. The request goes to the LLM, by hand or through a [`backend`].
+//! 3. [`convert_reply`] finds the assessment in the reply, parses it (YAML or Markdown) and
+//!    validates it against the rubric, and [`ExportFormat`] renders it for export.
+//!
+//! ```
+//! use automated_llama_text_generator::{convert_reply, gen_request_content, ExportFormat, PromptTemplate, Rubric};
+//!
+//! let rubric = Rubric::builtin();
+//! let request = gen_request_content(
+//!     &PromptTemplate::builtin(), &rubric,
+//!     "Write a Rust function that reverses a string".to_string(), String::new(),
+//!     0, 1, &[], false,
+//! )?;
+//! assert!(request.contains("reverses a string"));
+//!
+//! let reply = "# Experience\n- Basic Rust\n- Rating: Easy\n\
+//!              # Knowledge\n- Strings and chars\n- Rating: Easy - Medium\n\
+//!              # Ambiguity\n- Clear\n- Rating: Easy\n\
+//!              # Complexity\n- Unicode edge cases\n- Rating: Medium\n\
+//!              # Overall\n Difficulty Easy\n";
+//! let conversion = convert_reply(reply, &rubric, Some(3))?;
+//! let yaml = ExportFormat::Yaml.render(&conversion.assessment, true)?;
+//! assert!(yaml.contains("Rating: Easy - Medium"));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+/// Parsed assessments and their YAML/Markdown formats.
+pub mod assessment;
+/// LLM backends, the background job runner and the backend settings file.
+pub mod backend;
+/// Token estimates, per-model context limits and fitting a request into them.
+pub mod budget;
+/// Batch assessment of JSONL/CSV prompt files with resumable output.
+pub mod batch;
+/// Soft checks on an assessment: consistency and point limits.
+pub mod check;
+/// Conversion of LLM replies into validated assessments and export formats.
+pub mod convert;
+/// Locating the assessment inside a chat reply.
+pub mod extract;
+/// The few-shot example library appended to the full request.
+pub mod few_shot;
+/// Request templates, built-in and loaded from the template directory.
+pub mod prompt;
+/// The built-in rating scale and lenient parsing of ratings onto it.
+pub mod rating;
+/// `{PLACEHOLDER}` substitution used by the templates.
+pub mod render;
+/// Follow-up requests for replies that fail to convert.
+pub mod repair;
+/// Local BM25 similarity search, used to pick relevant examples.
+pub mod retrieval;
+/// Request text building: full, continuous, trim and repair requests.
+pub mod request;
+/// Rubric definition: categories, ratings and overall levels.
+pub mod rubric;
+/// Tracking what a chat already holds, to choose between the full and the short request.
+pub mod session;
+/// Token counting with a local Hugging Face tokenizer, or the estimate without one.
+pub mod tokens;
+#[cfg(test)]
+mod test_dir;
+
+pub use assessment::{Assessment, Category};
+pub use convert::{convert_reply, yaml_to_markdown, Conversion, ExportFormat};
+pub use prompt::PromptTemplate;
+pub use rating::Rating;
+pub use request::gen_request_content;
+pub use rubric::Rubric;
@@ -1,12 +1,45 @@
-mod prompt;
-mod raw_example;
-mod request;
-
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
 use eframe::egui;
 use egui::{ScrollArea, TextEdit};
 use clipboard::{ClipboardContext, ClipboardProvider};
-use serde_yaml::Value;
+use automated_llama_text_generator::{backend, convert, few_shot, prompt, repair, request, rubric, Rubric};
+use automated_llama_text_generator::budget::{self, Fit, RequestInput};
+use automated_llama_text_generator::backend::{BackendKind, BackendSettings, CancelToken, ChatFormat, ChatTemplateSettings, Job, Message, SamplingParams};
+use automated_llama_text_generator::batch::{self, Batch, BatchOptions, BatchProgress};
+use automated_llama_text_generator::few_shot::{Example, ExampleSelection};
+use automated_llama_text_generator::repair::RepairAttempt;
+use automated_llama_text_generator::session::{Session, Turn};
+use automated_llama_text_generator::tokens::TokenCounter;
+
+
+/// System clipboard, if there is one. Without it (e.g. over SSH without a display) the app
+/// still runs; copying reports an error and the text has to be selected by hand.
+struct Clipboard(Option<ClipboardContext>);
+
+impl Clipboard {
+    fn new() -> Self {
+        Self(ClipboardProvider::new().ok())
+    }
 
+    fn get(&mut self) -> Option<String> {
+        self.0.as_mut()?.get_contents().ok()
+    }
+
+    /// Copy `text`, returning the error to show when that is not possible.
+    fn set(&mut self, text: String) -> Result<(), PopupMessage> {
+        let copied = self.0.as_mut().is_some_and(|ctx| ctx.set_contents(text).is_ok());
+        if copied {
+            Ok(())
+        } else {
+            Err(PopupMessage::Error("The clipboard is not available, select and copy the text by hand.".to_string()))
+        }
+    }
+}
 
 struct InputField {
     text: String,
@@ -20,81 +53,956 @@ enum PopupMessage {
 }
 
 
+/// Message from a batch run's worker thread.
+enum BatchUpdate {
+    Progress(BatchProgress),
+    Done(anyhow::Result<BatchProgress>),
+}
+
+/// A batch running on its worker thread.
+struct BatchRun {
+    cancel: CancelToken,
+    updates: Receiver<BatchUpdate>,
+    progress: BatchProgress,
+}
+
+/// Example being added or edited in the Examples tab.
+struct ExampleEditor {
+    /// Id of the example being edited, `None` for a new one.
+    id: Option<String>,
+    example: Example,
+    /// Tags as typed, separated by commas.
+    tags: String,
+}
+
+/// The next request with the examples picked for it, measured by `refresh_request_preview`.
+struct RequestPreview {
+    /// Hash of everything the request is built from.
+    key: u64,
+    /// One explanation per example picked, none for a continuous request.
+    picks: Vec<String>,
+    fit: Result<Fit, String>,
+}
+
 struct GuiApp {
     input_fields: Vec<InputField>,
     result_text: String,
+    notices: Vec<String>,
+    too_long: Vec<(String, usize)>,
+    trim_request: Option<String>,
+    request_length: Option<usize>,
+    keep_unknown: bool,
     selected_tab: usize,
-    clipboard: ClipboardContext,
+    clipboard: Clipboard,
     selected_difficulty: usize,
     selected_prompt_length: usize,
+    templates: Vec<prompt::PromptTemplate>,
+    /// Template directories skipped on the last load, and why.
+    template_errors: Vec<String>,
+    selected_template: usize,
+    rubric: Rubric,
+    examples: Vec<Example>,
+    example_editor: Option<ExampleEditor>,
+    /// Use only the most relevant examples instead of every enabled one.
+    relevant_examples_only: bool,
+    relevant_example_count: usize,
+    backend: BackendSettings,
+    /// Counts the request tokens, with the tokenizer of the backend settings when one is set.
+    token_counter: TokenCounter,
+    /// Tokenizer path as typed, used once loaded.
+    tokenizer_path: String,
+    /// The request the next turn would send, kept until something it is built from changes.
+    request_preview: Option<RequestPreview>,
+    /// Bumped whenever the templates, the rubric or the example library change, as part of the
+    /// `request_preview` key.
+    library_generation: u64,
+    job: Option<Job>,
+    sent_request: String,
+    session: Session,
+    /// Turn of the running job, recorded in the session once the reply arrives.
+    pending_turn: Option<Turn>,
+    repair_enabled: bool,
+    max_repair_attempts: usize,
+    repair_log: Vec<RepairAttempt>,
+    ollama_models: Vec<String>,
+    pending_models: Option<Receiver<anyhow::Result<Vec<String>>>>,
+    batch_input: String,
+    batch_output: String,
+    batch_options: BatchOptions,
+    batch_run: Option<BatchRun>,
+    batch_summary: Option<String>,
     popup_state: Option<PopupMessage>,
 }
 
 impl GuiApp {
-    fn yaml_to_markdown(&self, yaml_str: &str) -> Result<String, String> {
-        // Parse YAML
-        let yaml: Value = serde_yaml::from_str(yaml_str)
-            .map_err(|e| format!("Invalid YAML format: {}", e))?;
+    /// Build the next request of the session: the full one for a new chat, the short one after.
+    fn start_turn(&mut self) -> Option<Turn> {
+        let template = &self.templates[self.selected_template];
+        let turn = self.session.start_turn(template, &self.rubric, |continuous| {
+            self.build_request(continuous).map(|fit| fit.request)
+        });
+        match turn {
+            Ok(turn) => {
+                self.request_length = Some(self.selected_prompt_length);
+                Some(turn)
+            }
+            Err(e) => {
+                self.popup_state = Some(PopupMessage::Error(
+                    format!("Failed to generate prompt: {:#}", e)
+                ));
+                None
+            }
+        }
+    }
 
-        let yaml_map = yaml.as_mapping()
-            .ok_or("Invalid YAML structure: expected mapping at root")?;
+    /// Send the request to the configured backend on a worker thread; the reply is picked up
+    /// by `poll_job` and goes straight into the converter.
+    fn send_request(&mut self) {
+        if let Some(turn) = self.start_turn() {
+            self.repair_log.clear();
+            let request = turn.request.clone();
+            self.pending_turn = Some(turn);
+            self.start_job(request);
+            if self.job.is_none() {
+                self.pending_turn = None;
+            }
+        }
+    }
 
-        let mut markdown = String::new();
+    fn copy_request(&mut self) {
+        if let Some(turn) = self.start_turn() {
+            match self.clipboard.set(turn.request.clone()) {
+                Ok(()) => self.session.finish_turn(turn, None),
+                Err(popup) => self.popup_state = Some(popup),
+            }
+        }
+    }
 
-        // Process each main section
-        for (key, value) in yaml_map {
-            let section_name = key.as_str()
-                .ok_or("Invalid section name")?;
+    /// Ask the backend to correct its last reply, quoting what was wrong with it. Returns
+    /// whether the repair was sent; it is not when the whole conversation would no longer fit
+    /// the context of the model.
+    fn send_repair(&mut self, problems: &[String]) -> bool {
+        let repair_request = request::gen_repair_request(
+            &self.templates[self.selected_template], &self.rubric,
+            self.input_fields[0].text.clone(), self.input_fields[1].text.clone(),
+            problems,
+        );
+        let repair_request = match repair_request {
+            Ok(repair_request) => repair_request,
+            Err(e) => {
+                self.popup_state = Some(PopupMessage::Error(format!("Failed to generate repair request: {:#}", e)));
+                return false;
+            }
+        };
+        let budget = self.backend.context.request_budget(self.backend.model_name());
+        let tokens = budget::message_tokens(
+            &self.job_messages(&repair_request), self.backend.chat_template(),
+            &|text: &str| self.token_counter.count(text),
+        );
+        match tokens {
+            Ok(tokens) if tokens <= budget => {
+                self.start_job(repair_request);
+                self.job.is_some()
+            }
+            Ok(tokens) => {
+                self.popup_state = Some(PopupMessage::Warning(format!(
+                    "The repair request would take {} of {} tokens, so the reply is used as it is.", tokens, budget
+                )));
+                false
+            }
+            Err(e) => {
+                self.popup_state = Some(PopupMessage::Error(format!("{:#}", e)));
+                false
+            }
+        }
+    }
 
-            // Skip processing if it's just "Overall"
-            if section_name == "Overall" {
-                continue;
+    /// `request` after the attempts in the repair log, and after the session history when the
+    /// pending turn continues the conversation.
+    fn job_messages(&self, request: &str) -> Vec<Message> {
+        let conversation = repair::conversation(&self.repair_log, request);
+        match &self.pending_turn {
+            Some(turn) => self.session.messages(turn, conversation),
+            None => conversation,
+        }
+    }
+
+    /// Send `request` as `job_messages` lays out the conversation.
+    fn start_job(&mut self, request: String) {
+        let backend = match self.backend.backend() {
+            Ok(backend) => backend,
+            Err(e) => {
+                self.popup_state = Some(PopupMessage::Error(format!("{:#}", e)));
+                return;
             }
+        };
+        let messages = self.job_messages(&request);
+        self.job = Some(Job::spawn(backend, messages));
+        self.sent_request = request;
+        // The reply streams into the Results tab
+        self.result_text.clear();
+        self.notices.clear();
+        self.too_long.clear();
+        self.trim_request = None;
+        self.selected_tab = 1;
+    }
 
-            // Add section header
-            markdown.push_str(&format!("# {}\n", section_name));
+    /// Stop waiting for the reply. A running command is killed and an HTTP request drops its
+    /// connection.
+    fn cancel_request(&mut self) {
+        if let Some(job) = self.job.take() {
+            job.cancel();
+        }
+        self.pending_turn = None;
+    }
 
-            if let Some(section_map) = value.as_mapping() {
-                for (subkey, subvalue) in section_map {
-                    let subkey_str = subkey.as_str()
-                        .ok_or("Invalid subsection name")?;
+    /// Spinner, elapsed time and Cancel button while a request is running.
+    fn job_status_ui(&mut self, ui: &mut egui::Ui) {
+        let Some(job) = &self.job else {
+            return;
+        };
+        let mut cancel = false;
+        ui.horizontal(|ui| {
+            ui.spinner();
+            ui.label(format!(
+                "Waiting for the backend... {:.1}s, {} characters received",
+                job.elapsed().as_secs_f32(), job.partial().chars().count()
+            ));
+            cancel = ui.button("Cancel").clicked();
+        });
+        if cancel {
+            self.cancel_request();
+        }
+    }
 
-                    match subkey_str {
-                        "Note" => {
-                            if let Some(notes) = subvalue.as_sequence() {
-                                for note in notes {
-                                    if let Some(note_str) = note.as_str() {
-                                        markdown.push_str(&format!("- {}\n", note_str));
-                                    }
-                                }
-                            }
-                        }
-                        "Rating" => {
-                            if let Some(rating) = subvalue.as_str() {
-                                markdown.push_str(&format!("- Rating: {}\n", rating));
-                            }
+    fn poll_job(&mut self) {
+        let Some(job) = &mut self.job else {
+            return;
+        };
+        let Some(result) = job.poll() else {
+            if job.partial().len() != self.result_text.len() {
+                self.result_text = job.partial().to_string();
+            }
+            return;
+        };
+        self.job = None;
+
+        match result {
+            Ok(reply) => {
+                let max_points = request::max_points(self.request_length.unwrap_or(self.selected_prompt_length));
+                let problems = repair::reply_problems(&reply, &self.rubric, max_points);
+                self.repair_log.push(RepairAttempt {
+                    request: std::mem::take(&mut self.sent_request),
+                    reply: reply.clone(),
+                    problems: problems.clone(),
+                });
+                // The first attempt is the request itself, the rest are repairs
+                if self.repair_enabled && !problems.is_empty() && self.repair_log.len() <= self.max_repair_attempts
+                    && self.send_repair(&problems) {
+                    return;
+                }
+                if let Some(turn) = self.pending_turn.take() {
+                    self.session.finish_turn(turn, Some(&reply));
+                }
+                self.input_fields[2].text = reply.clone();
+                self.convert_reply(&reply);
+            }
+            Err(e) => {
+                self.pending_turn = None;
+                self.popup_state = Some(PopupMessage::Error(format!("Backend request failed: {:#}", e)));
+            }
+        }
+    }
+
+    fn backend_settings_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Backend:");
+            egui::ComboBox::from_id_source("backend_selector")
+                .selected_text(self.backend.kind.label())
+                .show_ui(ui, |ui| {
+                    for kind in BackendKind::ALL {
+                        // Another backend is another conversation
+                        if ui.selectable_value(&mut self.backend.kind, kind, kind.label()).changed() {
+                            self.session.reset();
                         }
-                        _ => {}
                     }
+                });
+            if ui.button("💾 Save").clicked() {
+                let path = backend::settings_path();
+                self.popup_state = Some(match backend::save_settings(&path, &self.backend) {
+                    Ok(()) => PopupMessage::Success(format!("Saved backend settings to {}", path.display())),
+                    Err(e) => PopupMessage::Error(format!("Failed to save backend settings: {:#}", e)),
+                });
+            }
+        });
+
+        match self.backend.kind {
+            BackendKind::Clipboard | BackendKind::Mock => {}
+            BackendKind::OpenAi => {
+                let settings = &mut self.backend.openai;
+                egui::Grid::new("openai_settings").num_columns(2).show(ui, |ui| {
+                    ui.label("Endpoint:");
+                    ui.text_edit_singleline(&mut settings.endpoint);
+                    ui.end_row();
+                    ui.label("API key:");
+                    ui.add(TextEdit::singleline(&mut settings.api_key).password(true)
+                        .hint_text("$OPENAI_API_KEY"));
+                    ui.end_row();
+                    ui.label("Model:");
+                    ui.text_edit_singleline(&mut settings.model);
+                    ui.end_row();
+                    sampling_ui(ui, &mut settings.sampling);
+                });
+            }
+            BackendKind::Ollama => {
+                let fetching = self.pending_models.is_some();
+                let mut fetch_models = false;
+                let settings = &mut self.backend.ollama;
+                let models = &self.ollama_models;
+                egui::Grid::new("ollama_settings").num_columns(2).show(ui, |ui| {
+                    ui.label("Server:");
+                    ui.text_edit_singleline(&mut settings.base_url);
+                    ui.end_row();
+                    ui.label("Model:");
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut settings.model);
+                        egui::ComboBox::from_id_source("ollama_models")
+                            .selected_text("Installed")
+                            .show_ui(ui, |ui| {
+                                for model in models {
+                                    ui.selectable_value(&mut settings.model, model.clone(), model);
+                                }
+                            });
+                        fetch_models = ui.add_enabled(!fetching, egui::Button::new("🔄"))
+                            .on_hover_text("Fetch the installed models from the server")
+                            .clicked();
+                    });
+                    ui.end_row();
+                    sampling_ui(ui, &mut settings.sampling);
+                });
+                if fetch_models {
+                    self.fetch_ollama_models();
+                }
+            }
+            BackendKind::Command => {
+                let settings = &mut self.backend.command;
+                egui::Grid::new("command_settings").num_columns(2).show(ui, |ui| {
+                    ui.label("Command:");
+                    ui.add(TextEdit::singleline(&mut settings.command)
+                        .hint_text("llama-cli -m model.gguf --no-display-prompt -f /dev/stdin"))
+                        .on_hover_text("Run through the system shell; the request is written to stdin and stdout is the reply");
+                    ui.end_row();
+                    ui.label("Timeout (s):");
+                    ui.add(egui::DragValue::new(&mut settings.timeout_secs).clamp_range(1..=86400));
+                    ui.end_row();
+                    chat_template_ui(ui, &mut settings.chat_template);
+                });
+            }
+            BackendKind::LlamaCpp => {
+                let settings = &mut self.backend.llama_cpp;
+                egui::Grid::new("llama_cpp_settings").num_columns(2).show(ui, |ui| {
+                    ui.label("Server:");
+                    ui.text_edit_singleline(&mut settings.base_url);
+                    ui.end_row();
+                    chat_template_ui(ui, &mut settings.chat_template);
+                    sampling_ui(ui, &mut settings.sampling);
+                });
+            }
+        }
+    }
+
+    fn fetch_ollama_models(&mut self) {
+        let ollama = backend::OllamaBackend::new(self.backend.ollama.clone());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(ollama.list_models());
+        });
+        self.pending_models = Some(receiver);
+    }
+
+    fn poll_pending_models(&mut self) {
+        let Some(receiver) = &self.pending_models else {
+            return;
+        };
+        let result = match receiver.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => Err(anyhow::anyhow!("Model list worker stopped unexpectedly")),
+        };
+        self.pending_models = None;
+
+        match result {
+            Ok(models) if models.is_empty() => {
+                self.popup_state = Some(PopupMessage::Error("No models are installed on the Ollama server".to_string()));
+            }
+            Ok(models) => self.ollama_models = models,
+            Err(e) => {
+                self.popup_state = Some(PopupMessage::Error(format!("Failed to list Ollama models: {:#}", e)));
+            }
+        }
+    }
+
+    /// Assess the rows of the batch input file on a worker thread, with the template and
+    /// backend selected in the Input tab.
+    fn start_batch(&mut self) {
+        if self.batch_input.trim().is_empty() || self.batch_output.trim().is_empty() {
+            self.popup_state = Some(PopupMessage::Warning("Enter an input and an output file".to_string()));
+            return;
+        }
+        if self.backend.kind == BackendKind::Clipboard {
+            self.popup_state = Some(PopupMessage::Error(
+                "Batch mode needs a backend, select one in the Input tab".to_string()
+            ));
+            return;
+        }
+        let rows = match batch::read_rows(&PathBuf::from(self.batch_input.trim())) {
+            Ok(rows) => rows,
+            Err(e) => {
+                self.popup_state = Some(PopupMessage::Error(format!("{:#}", e)));
+                return;
+            }
+        };
+
+        let output = PathBuf::from(self.batch_output.trim());
+        let template = self.templates[self.selected_template].clone();
+        let rubric = self.rubric.clone();
+        let examples = self.examples.clone();
+        let selection = self.example_selection();
+        let settings = self.backend.clone();
+        let options = self.batch_options;
+        let cancel = CancelToken::default();
+        let token = cancel.clone();
+        let (sender, updates) = mpsc::channel();
+        let progress = BatchProgress { total: rows.len(), ..Default::default() };
+        thread::spawn(move || {
+            let batch = Batch { rows: &rows, template: &template, rubric: &rubric,
+                                examples: &examples, selection, options };
+            let result = batch.run(&|| settings.backend(), &output, &token, &mut |progress| {
+                let _ = sender.send(BatchUpdate::Progress(*progress));
+            });
+            let _ = sender.send(BatchUpdate::Done(result));
+        });
+        self.batch_run = Some(BatchRun { cancel, updates, progress });
+        self.batch_summary = None;
+    }
+
+    fn poll_batch(&mut self) {
+        let Some(run) = &mut self.batch_run else {
+            return;
+        };
+        let result = loop {
+            match run.updates.try_recv() {
+                Ok(BatchUpdate::Progress(progress)) => run.progress = progress,
+                Ok(BatchUpdate::Done(result)) => break result,
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => break Err(anyhow::anyhow!("Batch worker stopped unexpectedly")),
+            }
+        };
+        let cancelled = run.cancel.is_cancelled();
+        self.batch_run = None;
+
+        self.batch_summary = Some(match result {
+            Ok(progress) => {
+                let mut summary = format!(
+                    "{}: {} assessed, {} failed, {} already done, {} left.",
+                    if cancelled { "Cancelled" } else { "Finished" },
+                    progress.succeeded, progress.failed, progress.skipped,
+                    progress.total - progress.finished(),
+                );
+                if progress.failed > 0 || cancelled {
+                    summary.push_str(" Start again to retry the rest.");
+                }
+                summary
+            }
+            Err(e) => format!("Batch failed: {:#}", e),
+        });
+    }
+
+    fn batch_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label(
+            "Assess every prompt of a JSONL or CSV file with the template and backend selected \
+             in the Input tab. Columns: prompt, and optionally id, previous_turn, difficulty_pref \
+             and length_pref."
+        );
+        ui.add_space(8.0);
+        ui.add_enabled_ui(self.batch_run.is_none(), |ui| {
+            egui::Grid::new("batch_settings").num_columns(2).show(ui, |ui| {
+                ui.label("Input file:");
+                ui.text_edit_singleline(&mut self.batch_input);
+                ui.end_row();
+                ui.label("Output file:");
+                ui.text_edit_singleline(&mut self.batch_output)
+                    .on_hover_text("Results are appended as JSONL; rows that already have an assessment are skipped");
+                ui.end_row();
+                ui.label("Concurrency:");
+                ui.add(egui::DragValue::new(&mut self.batch_options.concurrency).clamp_range(1..=32));
+                ui.end_row();
+                ui.label("Requests per minute:");
+                ui.add(egui::DragValue::new(&mut self.batch_options.requests_per_minute).clamp_range(0..=10000))
+                    .on_hover_text("0 for no limit");
+                ui.end_row();
+            });
+        });
+
+        ui.add_space(8.0);
+        let mut cancel = false;
+        match &self.batch_run {
+            Some(run) => {
+                let progress = run.progress;
+                let fraction = if progress.total == 0 { 0.0 } else { progress.finished() as f32 / progress.total as f32 };
+                ui.add(egui::ProgressBar::new(fraction).text(format!(
+                    "{}/{} rows, {} failed", progress.finished(), progress.total, progress.failed
+                )));
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    cancel = ui.button("Cancel").clicked();
+                });
+            }
+            None => {
+                if ui.button("▶ Start Batch").clicked() {
+                    self.start_batch();
+                }
+            }
+        }
+        if cancel {
+            if let Some(run) = &self.batch_run {
+                run.cancel.cancel();
+            }
+        }
+        if let Some(summary) = &self.batch_summary {
+            ui.add_space(8.0);
+            ui.label(summary);
+        }
+    }
+
+    fn example_selection(&self) -> ExampleSelection {
+        if self.relevant_examples_only {
+            ExampleSelection::MostRelevant(self.relevant_example_count)
+        } else {
+            ExampleSelection::All
+        }
+    }
+
+    /// What the request for the current input is built from, with the examples picked for it.
+    fn request_input(&self, continuous: bool) -> RequestInput<'_> {
+        let (current_prompt, previous_turn) = (&self.input_fields[0].text, &self.input_fields[1].text);
+        RequestInput {
+            template: &self.templates[self.selected_template],
+            rubric: self.rubric.clone(),
+            current_prompt: current_prompt.clone(),
+            previous_turn: previous_turn.clone(),
+            preference_difficulty: self.selected_difficulty,
+            preference_length: self.selected_prompt_length,
+            examples: self.example_selection().select(&self.examples, current_prompt, previous_turn),
+            continuous,
+        }
+    }
+
+    /// The request for the current input, measured against the context of the selected model
+    /// along with the history and chat format sent with it, and shrunk to fit it when automatic
+    /// fitting is on.
+    fn build_request(&self, continuous: bool) -> anyhow::Result<Fit> {
+        let input = self.request_input(continuous);
+        let context = &self.backend.context;
+        let budget = context.request_budget(self.backend.model_name());
+        let count_tokens = |text: &str| self.token_counter.count(text);
+        // Only a continuous request goes out after the history, see `Session::messages`
+        let earlier = if continuous { self.session.history() } else { &[] };
+        let conversation = budget::conversation_tokens(earlier, self.backend.chat_template(), &count_tokens)?;
+        if context.auto_fit {
+            budget::fit_request(input, budget, conversation, &count_tokens)
+        } else {
+            budget::measure_request(input, budget, conversation, &count_tokens)
+        }
+    }
+
+    /// Measure the request the next turn would send. Picking the examples and counting the
+    /// tokens are too slow to redo every frame, so the request is only built and measured again
+    /// once something it is built from changed.
+    fn refresh_request_preview(&mut self) {
+        let kind = self.session.next_kind(&self.templates[self.selected_template], &self.rubric);
+        let context = &self.backend.context;
+        let mut hasher = DefaultHasher::new();
+        (&self.input_fields[0].text, &self.input_fields[1].text).hash(&mut hasher);
+        (self.library_generation, self.selected_template, self.selected_difficulty, self.selected_prompt_length).hash(&mut hasher);
+        (self.relevant_examples_only, self.relevant_example_count, kind.is_continuous()).hash(&mut hasher);
+        for message in self.session.history() {
+            message.content.hash(&mut hasher);
+        }
+        (context.request_budget(self.backend.model_name()), context.auto_fit, self.token_counter.path()).hash(&mut hasher);
+        serde_json::to_string(&self.backend.chat_template()).ok().hash(&mut hasher);
+        let key = hasher.finish();
+        if self.request_preview.as_ref().is_some_and(|preview| preview.key == key) {
+            return;
+        }
+        let picks = if kind.is_continuous() {
+            Vec::new()
+        } else {
+            self.example_selection()
+                .pick(&self.examples, &self.input_fields[0].text, &self.input_fields[1].text)
+                .iter()
+                .map(|pick| pick.explain())
+                .collect()
+        };
+        let fit = self.build_request(kind.is_continuous()).map_err(|e| format!("{:#}", e));
+        self.request_preview = Some(RequestPreview { key, picks, fit });
+    }
+
+    /// Load the tokenizer typed in its field, or go back to estimating when it is empty. The
+    /// path is kept in the backend settings.
+    fn load_tokenizer(&mut self) -> Result<(), String> {
+        let path = Some(self.tokenizer_path.trim())
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        self.token_counter = TokenCounter::from_setting(path.as_deref()).map_err(|e| format!("{:#}", e))?;
+        self.backend.context.tokenizer = path;
+        Ok(())
+    }
+
+    /// Tokens of each section of the next request, as measured by `refresh_request_preview`.
+    fn token_counts_ui(&self, ui: &mut egui::Ui) {
+        let Some(RequestPreview { fit: Ok(fit), .. }) = &self.request_preview else {
+            return;
+        };
+        ui.horizontal_wrapped(|ui| {
+            ui.label(format!("Tokens ({}):", self.token_counter.describe()));
+            for (name, tokens) in fit.sections.rows() {
+                ui.label(format!("{} {} ·", name, tokens));
+            }
+            let total = format!("total {} of {}", fit.tokens, fit.budget);
+            if fit.fits() {
+                ui.strong(total);
+            } else {
+                ui.colored_label(egui::Color32::RED, total);
+            }
+        });
+    }
+
+    /// Context window of the selected model, as it is saved with the backend settings.
+    fn context_ui(&mut self, ui: &mut egui::Ui) {
+        let model = self.backend.model_name().to_string();
+        let context = &mut self.backend.context;
+        ui.horizontal(|ui| {
+            ui.label(format!("Context of {}:", model));
+            let mut tokens = context.context_tokens(&model);
+            if ui.add(egui::DragValue::new(&mut tokens).clamp_range(512..=1_048_576).speed(64.0))
+                .on_hover_text("Save the backend settings to keep it")
+                .changed() {
+                context.models.insert(model, tokens);
+            }
+            ui.label("tokens, keeping");
+            ui.add(egui::DragValue::new(&mut context.reply_tokens).clamp_range(0..=65536).speed(16.0));
+            ui.label("for the reply");
+            ui.checkbox(&mut context.auto_fit, "Fit automatically")
+                .on_hover_text("Drop examples, shorten the rubric guide, then truncate the previous turn until the request fits");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Tokenizer:");
+            ui.add(TextEdit::singleline(&mut self.tokenizer_path)
+                .hint_text("tokenizer.json of the model, empty to estimate"));
+            if ui.button("Load").on_hover_text("Save the backend settings to keep it").clicked() {
+                self.popup_state = Some(match self.load_tokenizer() {
+                    Ok(()) => PopupMessage::Success(format!("Counting tokens: {}", self.token_counter.describe())),
+                    Err(e) => PopupMessage::Error(e),
+                });
+            }
+        });
+    }
+
+    /// The request the next Copy or Send would produce, and why it holds the examples it does.
+    fn request_preview_ui(&self, ui: &mut egui::Ui) {
+        let kind = self.session.next_kind(&self.templates[self.selected_template], &self.rubric);
+        ui.label(format!("Next request: {}", kind.describe()));
+        let Some(preview) = &self.request_preview else {
+            return;
+        };
+        if !kind.is_continuous() {
+            ui.strong(format!("Examples ({})", preview.picks.len()));
+            if preview.picks.is_empty() {
+                ui.label("No example is enabled, see the Examples tab");
+            }
+            for pick in &preview.picks {
+                ui.label(format!("• {}", pick));
+            }
+        }
+        match &preview.fit {
+            Ok(fit) => {
+                let size = format!("Size: {} of {} tokens ({})", fit.tokens, fit.budget, self.token_counter.describe());
+                if fit.fits() {
+                    ui.label(size);
+                } else {
+                    ui.colored_label(egui::Color32::RED, size + ", too long for the context");
+                }
+                for step in &fit.steps {
+                    ui.label(format!("• {}", step));
                 }
+                ScrollArea::vertical().id_source("request_preview").max_height(300.0).show(ui, |ui| {
+                    ui.add(TextEdit::multiline(&mut fit.request.as_str()).desired_width(f32::INFINITY));
+                });
+                if let Some(chat_template) = self.backend.chat_template() {
+                    egui::CollapsingHeader::new(format!("As sent to the model ({})", chat_template.format.label()))
+                        .show(ui, |ui| self.chat_preview_ui(ui, chat_template, &fit.request));
+                }
+            }
+            Err(e) => {
+                ui.colored_label(egui::Color32::RED, e);
             }
-            markdown.push('\n');
         }
+    }
 
-        // Add Overall at the end if it exists
-        if let Some(overall) = yaml_map.get(&Value::String("Overall".to_string())) {
-            if let Some(overall_str) = overall.as_str() {
-                markdown.push_str(&format!("# Overall\n Difficulty {}\n", overall_str));
+    /// The conversation so far and `request`, as the raw prompt backend receives them.
+    fn chat_preview_ui(&self, ui: &mut egui::Ui, chat_template: &ChatTemplateSettings, request: &str) {
+        let mut messages = self.session.history().to_vec();
+        messages.push(Message::user(request));
+        match chat_template.render(&messages) {
+            Ok(prompt) => {
+                ScrollArea::vertical().id_source("chat_preview").max_height(300.0).show(ui, |ui| {
+                    ui.add(TextEdit::multiline(&mut prompt.as_str()).desired_width(f32::INFINITY));
+                });
+            }
+            Err(e) => {
+                ui.colored_label(egui::Color32::RED, format!("{:#}", e));
             }
         }
+    }
 
-        Ok(markdown)
+    fn reload_examples(&mut self) -> Result<(), String> {
+        self.examples = few_shot::load_examples(&few_shot::example_dir())
+            .map_err(|e| format!("{:#}", e))?;
+        self.library_generation += 1;
+        Ok(())
+    }
+
+    /// Save the example in the editor, after checking its assessment against the rubric.
+    fn save_edited_example(&mut self) {
+        let Some(editor) = &self.example_editor else {
+            return;
+        };
+        let mut example = editor.example.clone();
+        example.tags = editor.tags.split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect();
+        example.id = editor.id.clone()
+            .unwrap_or_else(|| few_shot::new_id(&example.title, &self.examples));
+
+        let saved = example.validate(&self.rubric)
+            .and_then(|()| few_shot::save_example(&few_shot::example_dir(), &example))
+            .map_err(|e| format!("{:#}", e))
+            .and_then(|()| self.reload_examples());
+        match saved {
+            Ok(()) => self.example_editor = None,
+            Err(e) => self.popup_state = Some(PopupMessage::Error(format!("Failed to save the example: {}", e))),
+        }
+    }
+
+    fn examples_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "Checked examples go into the full request. Library: {}",
+                few_shot::example_dir().display()
+            ));
+            if ui.button("🔄 Reload").clicked() {
+                if let Err(e) = self.reload_examples() {
+                    self.popup_state = Some(PopupMessage::Error(format!("Failed to load examples: {}", e)));
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.relevant_examples_only, "Only the")
+                .on_hover_text("Rank the checked examples by similarity to the prompt and previous turn");
+            ui.add_enabled(
+                self.relevant_examples_only,
+                egui::DragValue::new(&mut self.relevant_example_count).clamp_range(1..=20),
+            );
+            ui.label("most relevant checked examples");
+        });
+        ui.add_space(8.0);
+
+        let mut changed = None;
+        let mut deleted = None;
+        egui::Grid::new("example_list").num_columns(4).striped(true).show(ui, |ui| {
+            for (i, example) in self.examples.iter_mut().enumerate() {
+                if ui.checkbox(&mut example.enabled, "").changed() {
+                    changed = Some(i);
+                    self.library_generation += 1;
+                }
+                ui.label(&example.title);
+                ui.label(example.tags.join(", "));
+                ui.horizontal(|ui| {
+                    if ui.button("✏ Edit").clicked() {
+                        self.example_editor = Some(ExampleEditor {
+                            id: Some(example.id.clone()),
+                            example: example.clone(),
+                            tags: example.tags.join(", "),
+                        });
+                    }
+                    if ui.button("🗑 Delete").clicked() {
+                        deleted = Some(example.id.clone());
+                    }
+                });
+                ui.end_row();
+            }
+        });
+        if ui.button("➕ New Example").clicked() {
+            self.example_editor = Some(ExampleEditor {
+                id: None,
+                example: Example {
+                    id: String::new(),
+                    title: String::new(),
+                    tags: Vec::new(),
+                    enabled: true,
+                    previous_answer: String::new(),
+                    prompt: self.input_fields[0].text.clone(),
+                    assessment: String::new(),
+                },
+                tags: String::new(),
+            });
+        }
+
+        let dir = few_shot::example_dir();
+        let update = match (changed, deleted) {
+            (Some(i), _) => Some(few_shot::save_example(&dir, &self.examples[i])),
+            (_, Some(id)) => Some(few_shot::delete_example(&dir, &id)),
+            _ => None,
+        };
+        if let Some(result) = update {
+            if let Err(e) = result.map_err(|e| format!("{:#}", e)).and_then(|()| self.reload_examples()) {
+                self.popup_state = Some(PopupMessage::Error(format!("Failed to update the example library: {}", e)));
+            }
+        }
+
+        let mut save = false;
+        let mut close = false;
+        if let Some(editor) = &mut self.example_editor {
+            ui.add_space(8.0);
+            ui.separator();
+            ui.strong(if editor.id.is_some() { "Edit example" } else { "New example" });
+            egui::Grid::new("example_editor").num_columns(2).show(ui, |ui| {
+                ui.label("Title:");
+                ui.text_edit_singleline(&mut editor.example.title);
+                ui.end_row();
+                ui.label("Tags:");
+                ui.text_edit_singleline(&mut editor.tags)
+                    .on_hover_text("Separated by commas, e.g. rust, networking");
+                ui.end_row();
+            });
+            for (label, text) in [
+                ("Previous answer (optional):", &mut editor.example.previous_answer),
+                ("Prompt:", &mut editor.example.prompt),
+                ("Expected assessment (Markdown):", &mut editor.example.assessment),
+            ] {
+                ui.label(label);
+                ui.add_sized([ui.available_width(), 100.0], TextEdit::multiline(text));
+            }
+            ui.horizontal(|ui| {
+                save = ui.button("💾 Save").clicked();
+                close = ui.button("Cancel").clicked();
+            });
+        }
+        if save {
+            self.save_edited_example();
+        }
+        if close {
+            self.example_editor = None;
+        }
+    }
+
+    fn reload_rubric(&mut self) -> Result<(), String> {
+        self.rubric = rubric::load_rubric(&rubric::rubric_path())
+            .map_err(|e| format!("{:#}", e))?;
+        self.library_generation += 1;
+        if self.selected_difficulty > self.rubric.overall.len() {
+            self.selected_difficulty = 0;
+        }
+        Ok(())
+    }
+
+    /// Load the templates again, keeping the selection. Broken ones are left out and listed in
+    /// `template_errors`.
+    fn reload_templates(&mut self) -> Result<usize, String> {
+        let prompt::LoadedTemplates { templates, errors } = prompt::load_templates(&prompt::template_dir())
+            .map_err(|e| format!("{:#}", e))?;
+        self.template_errors = errors.iter().map(|e| format!("{:#}", e)).collect();
+        let selected_name = self.templates.get(self.selected_template)
+            .map(|t| t.name.clone());
+
+        self.selected_template = selected_name
+            .and_then(|name| templates.iter().position(|t| t.name == name))
+            .unwrap_or(0);
+        self.templates = templates;
+        self.library_generation += 1;
+        Ok(self.templates.len() - 1)
+    }
+
+    /// Pull the assessment out of a raw LLM reply, convert it and show it in the Results tab,
+    /// listing whatever text around it was dropped.
+    fn convert_reply(&mut self, reply: &str) {
+        // Judge the answer by the length asked for when the request was copied
+        let preference_length = self.request_length.unwrap_or(self.selected_prompt_length);
+        let max_points = request::max_points(preference_length);
+        let conversion = match convert::convert_reply(reply, &self.rubric, max_points) {
+            Ok(conversion) => conversion,
+            Err(e) => {
+                self.popup_state = Some(PopupMessage::Error(format!("Failed to convert assessment: {:#}", e)));
+                return;
+            }
+        };
+
+        let markdown = conversion.assessment.to_markdown(self.keep_unknown);
+        let copied = self.clipboard.set(markdown.clone());
+        self.result_text = markdown;
+        self.notices = conversion.discarded.iter()
+            .map(|text| format!("Discarded from the reply:\n{}", text))
+            .collect();
+        if !conversion.unknown_keys.is_empty() {
+            self.notices.push(format!(
+                "Not part of the rubric ({}): {}",
+                if self.keep_unknown { "kept in the output" } else { "left out of the output" },
+                conversion.unknown_keys.join(", ")
+            ));
+        }
+        if copied.is_err() {
+            self.notices.push("The clipboard is not available, copy the result by hand.".to_string());
+        }
+        self.notices.extend(conversion.warnings.iter().map(|w| format!("Consistency: {}", w)));
+
+        self.too_long.clear();
+        self.trim_request = None;
+        if let (Some(max_points), false) = (max_points, conversion.too_long.is_empty()) {
+            let too_long: Vec<&str> = conversion.too_long.iter().map(String::as_str).collect();
+            self.trim_request = Some(request::gen_trim_request(&conversion.assessment, &too_long, max_points));
+            self.too_long = conversion.too_long.iter()
+                .map(|name| (name.clone(), max_points))
+                .collect();
+        }
+
+        if !self.too_long.is_empty() {
+            self.popup_state = Some(PopupMessage::Warning(format!(
+                "Converted, but {} category(ies) have more than the requested number of points. \
+                See the Results tab for a trim request.",
+                self.too_long.len()
+            )));
+        } else if !conversion.warnings.is_empty() {
+            self.popup_state = Some(PopupMessage::Warning(format!(
+                "Converted, but the assessment may not be consistent:\n{}",
+                conversion.warnings.join("\n")
+            )));
+        } else if !self.notices.is_empty() {
+            self.popup_state = Some(PopupMessage::Warning(format!(
+                "Converted with {} note(s) about text that was discarded or is not part of \
+                the rubric. See the Results tab.",
+                self.notices.len()
+            )));
+        }
+        self.selected_tab = 1;
     }
 }
 
 impl Default for GuiApp {
     fn default() -> Self {
-        Self {
+        let mut app = Self {
             input_fields: vec![
                 InputField {
                     text: String::new(),
@@ -106,21 +1014,93 @@ impl Default for GuiApp {
                 },
                 InputField {
                     text: String::new(),
-                    caption: "Enter the yaml file created by a LLM".to_string(),
+                    caption: "Enter the YAML or Markdown assessment created by a LLM".to_string(),
                 },
             ],
             result_text: String::new(),
+            notices: Vec::new(),
+            too_long: Vec::new(),
+            trim_request: None,
+            request_length: None,
+            keep_unknown: true,
             selected_tab: 0,
-            clipboard: ClipboardProvider::new().unwrap(),
+            clipboard: Clipboard::new(),
             selected_difficulty: 0,
             selected_prompt_length: 0,
+            templates: vec![prompt::PromptTemplate::builtin()],
+            template_errors: Vec::new(),
+            selected_template: 0,
+            rubric: Rubric::builtin(),
+            examples: few_shot::builtin_examples(),
+            example_editor: None,
+            relevant_examples_only: false,
+            relevant_example_count: 2,
+            backend: BackendSettings::default(),
+            token_counter: TokenCounter::estimate(),
+            tokenizer_path: String::new(),
+            request_preview: None,
+            library_generation: 0,
+            job: None,
+            sent_request: String::new(),
+            session: Session::default(),
+            pending_turn: None,
+            repair_enabled: false,
+            max_repair_attempts: 2,
+            repair_log: Vec::new(),
+            ollama_models: Vec::new(),
+            pending_models: None,
+            batch_input: String::new(),
+            batch_output: "batch_results.jsonl".to_string(),
+            batch_options: BatchOptions::default(),
+            batch_run: None,
+            batch_summary: None,
             popup_state: None,
+        };
+        match app.reload_templates() {
+            Ok(_) if !app.template_errors.is_empty() => {
+                app.popup_state = Some(PopupMessage::Warning(app.template_errors.join("\n")));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                app.popup_state = Some(PopupMessage::Error(
+                    format!("Failed to load templates, using the built-in one: {}", e)
+                ));
+            }
+        }
+        if let Err(e) = app.reload_examples() {
+            app.popup_state = Some(PopupMessage::Error(
+                format!("Failed to load examples, using the built-in ones: {}", e)
+            ));
+        }
+        if let Err(e) = app.reload_rubric() {
+            app.popup_state = Some(PopupMessage::Error(
+                format!("Failed to load rubric, using the built-in one: {}", e)
+            ));
         }
+        match backend::load_settings(&backend::settings_path()) {
+            Ok(settings) => app.backend = settings,
+            Err(e) => {
+                app.popup_state = Some(PopupMessage::Error(format!("{:#}", e)));
+            }
+        }
+        app.tokenizer_path = app.backend.context.tokenizer.as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        if let Err(e) = app.load_tokenizer() {
+            app.popup_state = Some(PopupMessage::Error(format!("{}, estimating tokens instead", e)));
+        }
+        app
     }
 }
 
 impl eframe::App for GuiApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_job();
+        self.poll_pending_models();
+        self.poll_batch();
+        if self.job.is_some() || self.pending_models.is_some() || self.batch_run.is_some() {
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
 
         let mut show_popup = true;
         if let Some(popup) = &self.popup_state {
@@ -156,6 +1136,12 @@ impl eframe::App for GuiApp {
                 if ui.selectable_label(self.selected_tab == 1, "Results").clicked() {
                     self.selected_tab = 1;
                 }
+                if ui.selectable_label(self.selected_tab == 2, "Batch").clicked() {
+                    self.selected_tab = 2;
+                }
+                if ui.selectable_label(self.selected_tab == 3, "Examples").clicked() {
+                    self.selected_tab = 3;
+                }
             });
 
             ui.separator();
@@ -163,6 +1149,7 @@ impl eframe::App for GuiApp {
             match self.selected_tab {
                 0 => {
                     // Input Tab
+                    self.refresh_request_preview();
                     ScrollArea::vertical().show(ui, |ui| {
                         for field in &mut self.input_fields[0..2] {
                             // Add some spacing between fields
@@ -177,7 +1164,7 @@ impl eframe::App for GuiApp {
 
                             // Paste button
                             if ui.button("📋 Paste").clicked() {
-                                if let Ok(clipboard_content) = self.clipboard.get_contents() {
+                                if let Some(clipboard_content) = self.clipboard.get() {
                                     field.text = clipboard_content;
                                 }
                             }
@@ -195,16 +1182,13 @@ impl eframe::App for GuiApp {
                             egui::ComboBox::from_id_source("difficulty_selector")
                                 .selected_text(match self.selected_difficulty {
                                     0 => "None",
-                                    1 => "Easy",
-                                    2 => "Medium",
-                                    3 => "Hard",
-                                    _ => "None",
+                                    i => self.rubric.overall.get(i - 1).map_or("None", |l| l.name.as_str()),
                                 })
                                 .show_ui(ui, |ui| {
                                     ui.selectable_value(&mut self.selected_difficulty, 0, "None");
-                                    ui.selectable_value(&mut self.selected_difficulty, 1, "Easy");
-                                    ui.selectable_value(&mut self.selected_difficulty, 2, "Medium");
-                                    ui.selectable_value(&mut self.selected_difficulty, 3, "Hard");
+                                    for (i, level) in self.rubric.overall.iter().enumerate() {
+                                        ui.selectable_value(&mut self.selected_difficulty, i + 1, &level.name);
+                                    }
                                 });
                         });
                         ui.horizontal(|ui| {
@@ -222,50 +1206,87 @@ impl eframe::App for GuiApp {
                                     ui.selectable_value(&mut self.selected_prompt_length, 2, "Long");
                                 });
                         });
-
-                        if ui.button("Copy Full Prompt").clicked() {
-
-                            let req_content = request::gen_request_content(
-                                self.input_fields[0].text.clone(), self.input_fields[1].text.clone(),
-                                self.selected_difficulty, self.selected_prompt_length,
-                                false,
-                            );
-                            match req_content {
-                                Ok(content) => {
-                                    let mut ctx: ClipboardContext = ClipboardProvider::new().unwrap();
-
-
-                                    ctx.set_contents(content.to_owned()).unwrap();
-
-                                }
-                                Err(e) => {
-                                    println!("Error: {:?}", e);
-                                }
+                        ui.horizontal(|ui| {
+                            ui.label("Template:");
+                            egui::ComboBox::from_id_source("template_selector")
+                                .selected_text(&self.templates[self.selected_template].name)
+                                .show_ui(ui, |ui| {
+                                    for (i, template) in self.templates.iter().enumerate() {
+                                        let source = template.source.as_ref()
+                                            .map(|p| p.display().to_string())
+                                            .unwrap_or_else(|| "Compiled into the application".to_string());
+                                        ui.selectable_value(&mut self.selected_template, i, &template.name)
+                                            .on_hover_text(source);
+                                    }
+                                });
+                            if ui.button("🔄 Reload").clicked() {
+                                self.popup_state = Some(match (self.reload_templates(), self.reload_rubric()) {
+                                    (Ok(count), Ok(())) => {
+                                        let loaded = format!(
+                                            "Loaded {} template(s) from {} and the rubric from {}",
+                                            count, prompt::template_dir().display(), rubric::rubric_path().display()
+                                        );
+                                        if self.template_errors.is_empty() {
+                                            PopupMessage::Success(loaded)
+                                        } else {
+                                            PopupMessage::Warning(format!("{}\n{}", loaded, self.template_errors.join("\n")))
+                                        }
+                                    }
+                                    (Err(e), _) => PopupMessage::Error(format!("Failed to load templates: {}", e)),
+                                    (_, Err(e)) => PopupMessage::Error(format!("Failed to load rubric: {}", e)),
+                                });
                             }
+                        });
+                        for error in &self.template_errors {
+                            ui.colored_label(egui::Color32::RED, error);
                         }
+                        self.context_ui(ui);
+                        self.token_counts_ui(ui);
+                        egui::CollapsingHeader::new("Request preview")
+                            .show(ui, |ui| self.request_preview_ui(ui));
 
-                        if ui.button("Copy Shorten Prompt").clicked() {
-
-                            let req_content = request::gen_request_content(
-                                self.input_fields[0].text.clone(), self.input_fields[1].text.clone(),
-                                self.selected_difficulty, self.selected_prompt_length,
-                                true,
-                            );
-                            match req_content {
-                                Ok(content) => {
-                                    let mut ctx: ClipboardContext = ClipboardProvider::new().unwrap();
-
-
-                                    ctx.set_contents(content.to_owned()).unwrap();
+                        ui.horizontal(|ui| {
+                            if ui.button("Copy Prompt").clicked() {
+                                self.copy_request();
+                            }
+                            if ui.button("🆕 New Chat")
+                                .on_hover_text("Send the full request next time, for a chat that does not hold it yet")
+                                .clicked() {
+                                self.session.reset();
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            let next_kind = self.session.next_kind(&self.templates[self.selected_template], &self.rubric);
+                            ui.label(format!(
+                                "Next: {}, {} turn(s) in this chat.",
+                                next_kind.describe(), self.session.turns()
+                            ));
+                            ui.label("Full request every");
+                            ui.add(egui::DragValue::new(&mut self.session.max_turns).clamp_range(0..=100))
+                                .on_hover_text("0 to only send it for a new chat or a template change");
+                            ui.label("turns");
+                        });
 
+                        ui.add_space(8.0);
+                        self.backend_settings_ui(ui);
+                        if self.backend.kind != BackendKind::Clipboard {
+                            ui.horizontal(|ui| {
+                                if ui.add_enabled(self.job.is_none(), egui::Button::new("Send Prompt")).clicked() {
+                                    self.send_request();
                                 }
-                                Err(e) => {
-                                    println!("Error: {:?}", e);
-                                }
-                            }
+                            });
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut self.repair_enabled, "Repair invalid replies")
+                                    .on_hover_text("Send the problems back and ask for a corrected answer");
+                                ui.add_enabled(
+                                    self.repair_enabled,
+                                    egui::DragValue::new(&mut self.max_repair_attempts).clamp_range(1..=10),
+                                );
+                                ui.label("attempts at most");
+                            });
+                            self.job_status_ui(ui);
                         }
 
-
                         ui.add_space(16.0);
                         let field = &mut self.input_fields[2];
                         ui.horizontal(|ui| {
@@ -275,7 +1296,7 @@ impl eframe::App for GuiApp {
                                     .hint_text(&field.caption),
                             );
                             if ui.button("📋 Paste").clicked() {
-                                if let Ok(clipboard_content) = self.clipboard.get_contents() {
+                                if let Some(clipboard_content) = self.clipboard.get() {
                                     field.text = clipboard_content;
                                 }
                             }
@@ -283,20 +1304,21 @@ impl eframe::App for GuiApp {
                         ui.label(&field.caption);
                         ui.separator();
 
-                        // YAML conversion buttons
+                        // Assessment conversion buttons
                         ui.horizontal(|ui| {
-                            if ui.button("Paste YAML").clicked() {
-                                if let Ok(clipboard_content) = self.clipboard.get_contents() {
+                            if ui.button("Paste Assessment").clicked() {
+                                if let Some(clipboard_content) = self.clipboard.get() {
                                     self.input_fields[2].text = clipboard_content;
                                 }
                             }
                             if ui.button("Clear").clicked() {
                                 self.input_fields[2].text.clear();
                             }
-                            if ui.button("Convert YAML to Markdown").clicked() {
+                            ui.checkbox(&mut self.keep_unknown, "Keep keys outside the rubric");
+                            if ui.button("Convert to Markdown").clicked() {
                                 let yaml_text = if self.input_fields[2].text.is_empty() {
                                     // If empty, try to get from clipboard
-                                    if let Ok(clipboard_content) = self.clipboard.get_contents() {
+                                    if let Some(clipboard_content) = self.clipboard.get() {
                                         self.input_fields[2].text = clipboard_content;
                                         self.input_fields[2].text.clone()
                                     } else {
@@ -307,22 +1329,10 @@ impl eframe::App for GuiApp {
                                 };
 
                                 if !yaml_text.is_empty() {
-                                    match self.yaml_to_markdown(&yaml_text) {
-                                        Ok(markdown) => {
-                                            let mut ctx: ClipboardContext = ClipboardProvider::new().unwrap();
-                                            ctx.set_contents(markdown.clone()).unwrap();
-                                            self.result_text = markdown;
-                                            self.selected_tab = 1;
-                                        }
-                                        Err(e) => {
-                                            self.popup_state = Some(PopupMessage::Error(
-                                                format!("Failed to convert YAML: {}", e)
-                                            ));
-                                        }
-                                    }
+                                    self.convert_reply(&yaml_text);
                                 } else {
                                     self.popup_state = Some(PopupMessage::Warning(
-                                        "No YAML content to convert".to_string()
+                                        "No assessment to convert".to_string()
                                     ));
                                 }
                             }
@@ -332,27 +1342,88 @@ impl eframe::App for GuiApp {
                 1 => {
                     // Results Tab
                     ScrollArea::vertical().show(ui, |ui| {
+                        self.job_status_ui(ui);
                         ui.add_sized(
                             [ui.available_width(), 200.0],
                             TextEdit::multiline(&mut self.result_text)
                                 .interactive(false),
                         );
 
+                        if !self.too_long.is_empty() {
+                            ui.add_space(8.0);
+                            ui.strong("Over the point limit");
+                            for (name, max_points) in &self.too_long {
+                                ui.colored_label(
+                                    egui::Color32::RED,
+                                    format!("{}: more than {} points", name, max_points),
+                                );
+                            }
+                            if let Some(trim_request) = &self.trim_request {
+                                if ui.button("📋 Copy Trim Request").clicked() {
+                                    if let Err(popup) = self.clipboard.set(trim_request.clone()) {
+                                        self.popup_state = Some(popup);
+                                    }
+                                }
+                            }
+                        }
+
+                        if !self.repair_log.is_empty() {
+                            ui.add_space(8.0);
+                            egui::CollapsingHeader::new(format!("Repair log ({} attempt(s))", self.repair_log.len()))
+                                .show(ui, |ui| {
+                                    for (i, attempt) in self.repair_log.iter().enumerate() {
+                                        let outcome = if attempt.problems.is_empty() {
+                                            "usable".to_string()
+                                        } else {
+                                            format!("{} problem(s)", attempt.problems.len())
+                                        };
+                                        egui::CollapsingHeader::new(format!("Attempt {}: {}", i + 1, outcome))
+                                            .show(ui, |ui| {
+                                                for problem in &attempt.problems {
+                                                    ui.colored_label(egui::Color32::RED, problem);
+                                                }
+                                                ui.label("Request:");
+                                                ui.add(TextEdit::multiline(&mut attempt.request.as_str())
+                                                    .desired_width(f32::INFINITY));
+                                                ui.label("Reply:");
+                                                ui.add(TextEdit::multiline(&mut attempt.reply.as_str())
+                                                    .desired_width(f32::INFINITY));
+                                            });
+                                    }
+                                });
+                        }
+
+                        if !self.notices.is_empty() {
+                            ui.add_space(8.0);
+                            ui.strong("Conversion notes");
+                            for notice in &self.notices {
+                                ui.label(notice);
+                            }
+                        }
+
                         ui.add_space(8.0);
                         ui.horizontal(|ui| {
-                            if ui.button("📋 Copy to Clipboard").clicked() {
-                                if !self.result_text.is_empty() {
-                                    self.clipboard.set_contents(self.result_text.clone()).unwrap();
+                            if ui.button("📋 Copy to Clipboard").clicked() && !self.result_text.is_empty() {
+                                if let Err(popup) = self.clipboard.set(self.result_text.clone()) {
+                                    self.popup_state = Some(popup);
                                 }
                             }
 
                             if ui.button("🔄 Reset All").clicked() {
+                                // A reply still on its way belongs to the old state
+                                self.cancel_request();
                                 // Clear all input fields
                                 for field in &mut self.input_fields {
                                     field.text.clear();
                                 }
                                 // Reset result text
                                 self.result_text.clear();
+                                self.notices.clear();
+                                self.too_long.clear();
+                                self.trim_request = None;
+                                self.request_length = None;
+                                self.repair_log.clear();
+                                self.session.reset();
                                 // Reset difficulty selection
                                 self.selected_difficulty = 0;
 
@@ -363,12 +1434,78 @@ impl eframe::App for GuiApp {
                         });
                     });
                 }
+                2 => {
+                    // Batch Tab
+                    ScrollArea::vertical().show(ui, |ui| self.batch_ui(ui));
+                }
+                3 => {
+                    // Examples Tab
+                    ScrollArea::vertical().show(ui, |ui| self.examples_ui(ui));
+                }
                 _ => unreachable!(),
             }
         });
     }
 }
 
+/// Grid rows for the chat format of the backends that take a raw prompt.
+fn chat_template_ui(ui: &mut egui::Ui, chat_template: &mut ChatTemplateSettings) {
+    ui.label("Chat format:");
+    egui::ComboBox::from_id_source("chat_format")
+        .selected_text(chat_template.format.label())
+        .show_ui(ui, |ui| {
+            for format in ChatFormat::ALL {
+                ui.selectable_value(&mut chat_template.format, format, format.label());
+            }
+        });
+    ui.end_row();
+    if chat_template.format == ChatFormat::TokenizerConfig {
+        ui.label("tokenizer_config.json:");
+        let mut path = chat_template.tokenizer_config.as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        if ui.text_edit_singleline(&mut path).changed() {
+            chat_template.tokenizer_config = Some(path).filter(|path| !path.is_empty()).map(PathBuf::from);
+        }
+        ui.end_row();
+    }
+    ui.label("System message:");
+    ui.add(TextEdit::multiline(&mut chat_template.system).desired_rows(2)
+        .hint_text("None"));
+    ui.end_row();
+}
+
+/// Grid rows for the sampling parameters, shared by the backend settings panels.
+fn sampling_ui(ui: &mut egui::Ui, sampling: &mut SamplingParams) {
+    ui.label("Temperature:");
+    ui.add(egui::Slider::new(&mut sampling.temperature, 0.0..=2.0));
+    ui.end_row();
+    ui.label("Top P:");
+    ui.add(egui::Slider::new(&mut sampling.top_p, 0.0..=1.0));
+    ui.end_row();
+    ui.label("Top K:");
+    ui.add(egui::DragValue::new(&mut sampling.top_k).clamp_range(0..=1000));
+    ui.end_row();
+    ui.label("Repeat penalty:");
+    ui.add(egui::Slider::new(&mut sampling.repeat_penalty, 0.5..=2.0));
+    ui.end_row();
+    ui.label("Max tokens:");
+    ui.add(egui::DragValue::new(&mut sampling.max_tokens).clamp_range(0..=32768))
+        .on_hover_text("0 leaves the limit to the server");
+    ui.end_row();
+    ui.label("Seed:");
+    ui.horizontal(|ui| {
+        let mut fixed = sampling.seed.is_some();
+        if ui.checkbox(&mut fixed, "Fixed").changed() {
+            sampling.seed = fixed.then_some(0);
+        }
+        if let Some(seed) = &mut sampling.seed {
+            ui.add(egui::DragValue::new(seed));
+        }
+    });
+    ui.end_row();
+}
+
 fn main() -> eframe::Result<()> {
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -1,11 +1,18 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use crate::render;
+use crate::rubric::{self, Rubric};
+
 
 const TEMPLATE_ORIGINAL: &str = "Your mission is to produce a markdown copy ready format answer that is in the below markdown format \
 I repeat that it must be raw markdown, copy ready, as there is a copy button for me to copy the result. Raw markdown.
-that rates a llm prompt on 4 category Experience, Knowledge, Ambiguity and Complexity. Each category \
+that rates a llm prompt on {CATEGORY_COUNT} category {CATEGORIES}. Each category \
 has 2 type of attributes. First type is an array of string, contain points what you want to talk \
-about the category. And there is the second type, Rating, to conclude what should the category be ranked as. It can be one of: Easy, \
-Easy - Medium, Medium, Medium - Hard, Hard and Very Hard.\
-There should be the Overall rating also, which strictly falls into Difficulty of Easy, Medium or Hard.\
+about the category. And there is the second type, Rating, to conclude what should the category be ranked as. It can be one of: \
+{RATINGS}.\
+There should be the Overall rating also, which strictly falls into Difficulty of {OVERALL_LEVELS}.\
 Sample response (do not include the backtick in the answer.
 ```
 # Experience
@@ -36,30 +43,7 @@ The previous turn answer:
 ---
 Some overall guide on what to decide on the prompt:
 
-Easy:
-
-Experience level: undergraduate level
-Knowledge required: limited domain/algorithmics knowledge or implementation context (architecture, libraries, pre-existing code)
-Ambiguity of prompt: little ambiguity in the question (in case of underspecification, good default behaviors are easy to come up with or not important), limited complexity of specifications (in #instructions)
-Complexity of solution: solution is easy to explain (e.g., code doesn’t need comments to be understood) and to test for/debug (limited corner cases)
-
-
-Medium:
-
-Experience level: masters level
-Knowledge required: may require knowledge of standard algorithms and data structures to get an optimal solution, knowledge of common libraries and concepts or additional code context.
-Ambiguity of prompt: medium ambiguity in the prompt (e.g., needs to come up with reasonable ad-hoc data representation or class structure without explicit guidance), multiple requirements should be satisfied or multiple bugs should be found
-Complexity of solution: involves corner cases that should be dealt with separately; explanation of the solution requires some abstraction or decomposition of the problem into a few subproblems
-
-
-Hard:
-
-Experience level: domain expert question
-Knowledge required: require expert domain knowledge, or information on the specific application or deployment scenario, including substantial specific API/code context
-Ambiguity of prompt: finding good solutions need non-trivial design decisions regarding data structures, algorithms or code architecture/design patterns
-Complexity of solution: finding a solution requires solving several non-trivial subproblems or finding non-trivial bugs; problem involves tricky corner cases, explaining the solution to a non-expert requires adding context
-
-Some example just for you
+{RUBRIC_GUIDE}Some example just for you
 ";
 
 const TEMPLATE_NEXT : &str = "Do the same thing\n\
@@ -72,24 +56,204 @@ The previous turn answer:
 ---
 ";
 
-pub fn generate_chat_gpt_prompt(current_prompt: String, previous_turn: String) -> String {
-    let prev_turn_str = if previous_turn.is_empty() {
-        "(none)".to_string()
-    }else{
-        previous_turn
-    };
-    TEMPLATE_ORIGINAL
-        .replace("{CURRENT_PROMPT}", &current_prompt)
-        .replace("{PREVIOUS_TURN_ANSWER}", &prev_turn_str)
+/// Name under which the built-in template is listed.
+pub const BUILTIN_TEMPLATE_NAME: &str = "Built-in";
+
+/// A pair of request templates: the full rubric sent on the first turn and
+/// the short "do the same thing" text sent once the chat already holds it.
+#[derive(Clone, Debug)]
+pub struct PromptTemplate {
+    pub name: String,
+    pub original: String,
+    pub next: String,
+    pub source: Option<PathBuf>,
+}
+
+impl PromptTemplate {
+    pub fn builtin() -> Self {
+        Self {
+            name: BUILTIN_TEMPLATE_NAME.to_string(),
+            original: TEMPLATE_ORIGINAL.to_string(),
+            next: TEMPLATE_NEXT.to_string(),
+            source: None,
+        }
+    }
+
+    /// Load a template from a directory holding `original.md` and, optionally, `next.md`.
+    /// A missing `next.md` falls back to the built-in continuous text.
+    pub fn from_dir(dir: &Path) -> Result<Self> {
+        let name = dir.file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| anyhow!("Invalid template directory name: {}", dir.display()))?
+            .to_string();
+
+        let original_path = dir.join(ORIGINAL_FILE);
+        let original = fs::read_to_string(&original_path)
+            .with_context(|| format!("Failed to read {}", original_path.display()))?;
+
+        let next_path = dir.join(NEXT_FILE);
+        let next = if next_path.exists() {
+            fs::read_to_string(&next_path)
+                .with_context(|| format!("Failed to read {}", next_path.display()))?
+        } else {
+            TEMPLATE_NEXT.to_string()
+        };
+
+        let template = Self {
+            name,
+            original,
+            next,
+            source: Some(dir.to_path_buf()),
+        };
+        template.validate()?;
+        Ok(template)
+    }
+
+    /// Render both texts with empty values so a broken template is reported when it is
+    /// loaded rather than the first time it is used.
+    pub fn validate(&self) -> Result<()> {
+        let rubric = Rubric::builtin();
+        generate_chat_gpt_prompt(self, &rubric, String::new(), String::new())?;
+        generate_chat_gpt_prompt_continuous(self, &rubric, String::new(), String::new())?;
+        Ok(())
+    }
+}
+
+const ORIGINAL_FILE: &str = "original.md";
+const NEXT_FILE: &str = "next.md";
+
+/// Directory the user/team templates are read from: `$QAG_TEMPLATE_DIR`, or `templates`
+/// relative to the working directory.
+pub fn template_dir() -> PathBuf {
+    env::var_os("QAG_TEMPLATE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("templates"))
+}
+
+/// Templates read by `load_templates`, with the reasons the broken ones were skipped.
+pub struct LoadedTemplates {
+    pub templates: Vec<PromptTemplate>,
+    pub errors: Vec<anyhow::Error>,
+}
+
+/// Built-in template first, followed by every template found in `dir`, sorted by name.
+/// A missing directory is not an error, it just means there are no custom templates. A
+/// directory that does not hold a valid template is skipped, so it cannot hide the others.
+pub fn load_templates(dir: &Path) -> Result<LoadedTemplates> {
+    let mut loaded = LoadedTemplates { templates: vec![PromptTemplate::builtin()], errors: Vec::new() };
+    if !dir.is_dir() {
+        return Ok(loaded);
+    }
+
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+
+    for path in dirs {
+        match PromptTemplate::from_dir(&path) {
+            Ok(template) => loaded.templates.push(template),
+            Err(e) => loaded.errors.push(e.context(format!("Skipped template {}", path.display()))),
+        }
+    }
+    Ok(loaded)
+}
+
+/// Full request text: the template's `original` text rendered with the rubric and the prompt.
+pub fn generate_chat_gpt_prompt(template: &PromptTemplate, rubric: &Rubric, current_prompt: String,
+                                previous_turn: String) -> Result<String> {
+    render_prompt(&template.original, rubric, &current_prompt, &previous_turn)
+        .with_context(|| format!("Template \"{}\" (full prompt)", template.name))
 }
 
-pub fn generate_chat_gpt_prompt_continuous(current_prompt: String, previous_turn: String) -> String {
+/// Short request text for a chat that already holds the full one: the template's `next` text.
+pub fn generate_chat_gpt_prompt_continuous(template: &PromptTemplate, rubric: &Rubric, current_prompt: String,
+                                           previous_turn: String) -> Result<String> {
+    render_prompt(&template.next, rubric, &current_prompt, &previous_turn)
+        .with_context(|| format!("Template \"{}\" (continuous prompt)", template.name))
+}
+
+const REQUIRED_PLACEHOLDERS: &[&str] = &["CURRENT_PROMPT"];
+
+fn render_prompt(template: &str, rubric: &Rubric, current_prompt: &str, previous_turn: &str) -> Result<String> {
     let prev_turn_str = if previous_turn.is_empty() {
-        "(none)".to_string()
+        "(none)"
     }else{
         previous_turn
     };
-    TEMPLATE_NEXT
-        .replace("{CURRENT_PROMPT}", &current_prompt)
-        .replace("{PREVIOUS_TURN_ANSWER}", &prev_turn_str)
-}
\ No newline at end of file
+    let ratings: Vec<&str> = rubric.ratings.iter().map(String::as_str).collect();
+    render::render_template(template, &[
+        ("CURRENT_PROMPT", current_prompt),
+        ("PREVIOUS_TURN_ANSWER", prev_turn_str),
+        ("CATEGORY_COUNT", &rubric.categories.len().to_string()),
+        ("CATEGORIES", &rubric::join_list(&rubric.category_names(), "and")),
+        ("RATINGS", &rubric::join_list(&ratings, "and")),
+        ("OVERALL_LEVELS", &rubric::join_list(&rubric.overall_names(), "or")),
+        ("RUBRIC_GUIDE", &rubric.guide()),
+    ], REQUIRED_PLACEHOLDERS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_load_templates_from_dir() {
+        let dir = TestDir::new("templates");
+        fs::create_dir_all(dir.join("team")).unwrap();
+        fs::write(dir.join("team").join(ORIGINAL_FILE), "Rate this: {CURRENT_PROMPT}").unwrap();
+        // A broken template is reported without taking the others down with it
+        fs::create_dir_all(dir.join("broken")).unwrap();
+        fs::write(dir.join("broken").join(ORIGINAL_FILE), "Rate this: {CURRENT_PROMT}").unwrap();
+        fs::create_dir_all(dir.join("empty")).unwrap();
+
+        let LoadedTemplates { templates, errors } = load_templates(dir.path()).unwrap();
+
+        assert_eq!(errors.len(), 2);
+        assert!(format!("{:#}", errors[0]).starts_with("Skipped template "));
+        assert!(format!("{:#}", errors[0]).contains("{CURRENT_PROMT}"));
+        assert!(format!("{:#}", errors[1]).contains(ORIGINAL_FILE));
+        assert_eq!(templates.len(), 2);
+        assert_eq!(templates[0].name, BUILTIN_TEMPLATE_NAME);
+        assert_eq!(templates[1].name, "team");
+        assert_eq!(templates[1].next, TEMPLATE_NEXT);
+        assert_eq!(
+            generate_chat_gpt_prompt(&templates[1], &Rubric::builtin(), "hello".to_string(), String::new()).unwrap(),
+            "Rate this: hello"
+        );
+    }
+
+    #[test]
+    fn test_invalid_template_is_rejected_on_load() {
+        let dir = TestDir::new("bad_template");
+        fs::write(dir.join(ORIGINAL_FILE), "Rate this: {CURRENT_PROMT}").unwrap();
+
+        let result = PromptTemplate::from_dir(dir.path());
+
+        assert!(format!("{:#}", result.unwrap_err()).contains("{CURRENT_PROMT}"));
+    }
+
+    #[test]
+    fn test_rubric_drives_request_text() {
+        let mut rubric = Rubric::builtin();
+        let builtin = generate_chat_gpt_prompt(&PromptTemplate::builtin(), &rubric, "p".to_string(), String::new())
+            .unwrap();
+        assert!(builtin.contains("on 4 category Experience, Knowledge, Ambiguity and Complexity."));
+        assert!(builtin.contains("Difficulty of Easy, Medium or Hard."));
+
+        rubric.categories.push(rubric::RubricCategory {
+            name: "Security".to_string(),
+            label: "Security impact".to_string(),
+        });
+        rubric.overall[0].guide.insert("Security".to_string(), "no attack surface".to_string());
+        let extended = generate_chat_gpt_prompt(&PromptTemplate::builtin(), &rubric, "p".to_string(), String::new())
+            .unwrap();
+        assert!(extended.contains("on 5 category Experience, Knowledge, Ambiguity, Complexity and Security."));
+        assert!(extended.contains("Security impact: no attack surface\n"));
+    }
+}
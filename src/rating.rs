@@ -0,0 +1,216 @@
+use std::fmt;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Built-in rating scale, the one the shipped rubric uses. Parsing is lenient (see
+/// `Rating::normalize`), printing always gives the canonical label. A rubric may define levels of
+/// its own; this scale only helps map loose wording onto the built-in ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum Rating {
+    #[serde(rename = "Easy")]
+    Easy,
+    #[serde(rename = "Easy - Medium")]
+    EasyMedium,
+    #[serde(rename = "Medium")]
+    Medium,
+    #[serde(rename = "Medium - Hard")]
+    MediumHard,
+    #[serde(rename = "Hard")]
+    Hard,
+    #[serde(rename = "Very Hard")]
+    VeryHard,
+}
+
+/// Words accepted in place of a base level, checked after exact matches and before typos.
+const SYNONYMS: &[(&str, Rating)] = &[
+    ("simple", Rating::Easy),
+    ("trivial", Rating::Easy),
+    ("moderate", Rating::Medium),
+    ("intermediate", Rating::Medium),
+    ("average", Rating::Medium),
+    ("mid", Rating::Medium),
+    ("difficult", Rating::Hard),
+    ("challenging", Rating::Hard),
+    ("expert", Rating::VeryHard),
+    ("extreme", Rating::VeryHard),
+];
+
+impl Rating {
+    pub const ALL: [Rating; 6] = [
+        Rating::Easy,
+        Rating::EasyMedium,
+        Rating::Medium,
+        Rating::MediumHard,
+        Rating::Hard,
+        Rating::VeryHard,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rating::Easy => "Easy",
+            Rating::EasyMedium => "Easy - Medium",
+            Rating::Medium => "Medium",
+            Rating::MediumHard => "Medium - Hard",
+            Rating::Hard => "Hard",
+            Rating::VeryHard => "Very Hard",
+        }
+    }
+
+    /// Position on the scale, 0 for Easy up to 5 for Very Hard.
+    pub fn step(&self) -> usize {
+        Rating::ALL.iter().position(|r| r == self).unwrap_or(0)
+    }
+
+    /// Map free-form LLM output onto the scale: case, dash style (`-`, `–`, `—`, `/`, `to`) and
+    /// spacing are ignored, common synonyms such as "Moderate" are accepted and a single word
+    /// one edit away from a level name is treated as a typo. Anything else is an error rather
+    /// than a guess.
+    pub fn normalize(text: &str) -> Result<Self> {
+        let key = rating_key(text);
+        let parts: Vec<&str> = key.split('-').collect();
+        let rating = match parts.as_slice() {
+            [single] => Self::normalize_level(single),
+            [first, second] if *first == "very" => Self::normalize_level(&format!("very {}", second)),
+            [first, second] => match (Self::normalize_level(first), Self::normalize_level(second)) {
+                (Some(a), Some(b)) => Self::between(a, b),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        rating.ok_or_else(|| anyhow!(
+            "Unknown rating \"{}\", expected one of: {}",
+            text.trim(), Rating::ALL.map(|r| r.as_str()).join(", ")
+        ))
+    }
+
+    /// A single level: Easy, Medium, Hard or Very Hard, or a synonym/typo of one.
+    fn normalize_level(word: &str) -> Option<Self> {
+        const LEVELS: [(&str, Rating); 4] = [
+            ("easy", Rating::Easy),
+            ("medium", Rating::Medium),
+            ("hard", Rating::Hard),
+            ("very hard", Rating::VeryHard),
+        ];
+
+        if let Some((_, rating)) = LEVELS.iter().chain(SYNONYMS).find(|(name, _)| *name == word) {
+            return Some(*rating);
+        }
+        if let Some(rest) = word.strip_prefix("very ") {
+            return match Self::normalize_level(rest) {
+                Some(Rating::Hard) => Some(Rating::VeryHard),
+                _ => None,
+            };
+        }
+
+        LEVELS.iter()
+            .find(|(name, _)| edit_distance(name, word) <= 1)
+            .map(|(_, rating)| *rating)
+    }
+
+    /// Combined rating for "A - B", in either order. The scale has no step between Hard and
+    /// Very Hard, so that pair is not a rating.
+    fn between(a: Rating, b: Rating) -> Option<Self> {
+        match (a.min(b), a.max(b)) {
+            (low, high) if low == high => Some(low),
+            (Rating::Easy, Rating::Medium) => Some(Rating::EasyMedium),
+            (Rating::Medium, Rating::Hard) => Some(Rating::MediumHard),
+            _ => None,
+        }
+    }
+}
+
+/// `text` lowercased, with its dash style (`-`, `–`, `—`, `/`, `to`), spacing and emphasis
+/// ignored: "**Medium – Hard**" gives "medium-hard". Two labels with the same key are the same.
+pub fn rating_key(text: &str) -> String {
+    text.trim()
+        .trim_matches(|c: char| c == '*' || c == '"' || c == '\'' || c == '.')
+        .to_lowercase()
+        .replace(['–', '—', '/'], "-")
+        .replace(" to ", "-")
+        .split('-')
+        .map(|part| part.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Optimal string alignment distance, so a swapped pair of letters counts as one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+impl fmt::Display for Rating {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Rating {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Rating::normalize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rating {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Rating::normalize(&text).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_variants() {
+        for (text, expected) in [
+            ("Medium - Hard", Rating::MediumHard),
+            ("medium-hard", Rating::MediumHard),
+            ("Medium – Hard", Rating::MediumHard),
+            ("hard/medium", Rating::MediumHard),
+            ("easy to medium", Rating::EasyMedium),
+            ("**Easy-Medium**", Rating::EasyMedium),
+            ("Moderate", Rating::Medium),
+            ("Meduim", Rating::Medium),
+            ("VERY  hard", Rating::VeryHard),
+            ("Very Hrad", Rating::VeryHard),
+        ] {
+            assert_eq!(Rating::normalize(text).unwrap(), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_normalize_rejects() {
+        for text in [
+            "Easy - Hard", "Impossible", "", "Very Easy", "Medium - Hard - Very Hard",
+            // Not a step of the scale, nor worth a guess
+            "Hard - Very Hard", "Low", "High", "Eeasyy", "Mediocre",
+        ] {
+            assert!(Rating::normalize(text).is_err(), "{}", text);
+        }
+    }
+}
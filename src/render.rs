@@ -0,0 +1,110 @@
+use anyhow::{anyhow, Result};
+
+/// Render `template` by substituting every `{NAME}` placeholder (upper case letters, digits and
+/// underscores) in a single left-to-right pass. Substituted values are copied verbatim and never
+/// scanned again, so user content containing placeholder text comes out untouched.
+///
+/// Any placeholder without a value is an error, as is a `required` placeholder that never
+/// appears in the template. Braces that do not form a placeholder are left as they are.
+pub fn render_template(template: &str, values: &[(&str, &str)], required: &[&str]) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut unknown: Vec<&str> = Vec::new();
+    let mut used: Vec<&str> = Vec::new();
+
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        match placeholder_name(after) {
+            Some(name) => {
+                match values.iter().find(|(key, _)| *key == name) {
+                    Some((_, value)) => output.push_str(value),
+                    None => {
+                        if !unknown.contains(&name) {
+                            unknown.push(name);
+                        }
+                    }
+                }
+                if !used.contains(&name) {
+                    used.push(name);
+                }
+                rest = &after[name.len() + 1..];
+            }
+            None => {
+                output.push('{');
+                rest = after;
+            }
+        }
+    }
+    output.push_str(rest);
+
+    let missing: Vec<&str> = required.iter()
+        .copied()
+        .filter(|name| !used.contains(name))
+        .collect();
+
+    let mut problems = Vec::new();
+    if !unknown.is_empty() {
+        problems.push(format!("unknown placeholder(s) {}", braced(&unknown)));
+    }
+    if !missing.is_empty() {
+        problems.push(format!("missing placeholder(s) {}", braced(&missing)));
+    }
+    if !problems.is_empty() {
+        return Err(anyhow!("Invalid template: {}", problems.join("; ")));
+    }
+
+    Ok(output)
+}
+
+/// Name of the placeholder at the start of `s` (just after the opening brace), if any.
+fn placeholder_name(s: &str) -> Option<&str> {
+    let end = s.find('}')?;
+    let name = &s[..end];
+    let valid = name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+    if valid {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+fn braced(names: &[&str]) -> String {
+    names.iter()
+        .map(|name| format!("{{{}}}", name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_are_not_rescanned() {
+        let rendered = render_template(
+            "P: {CURRENT_PROMPT}\nA: {PREVIOUS_TURN_ANSWER}",
+            &[("CURRENT_PROMPT", "see {PREVIOUS_TURN_ANSWER}"), ("PREVIOUS_TURN_ANSWER", "(none)")],
+            &["CURRENT_PROMPT"],
+        ).unwrap();
+        assert_eq!(rendered, "P: see {PREVIOUS_TURN_ANSWER}\nA: (none)");
+    }
+
+    #[test]
+    fn test_non_placeholder_braces_are_kept() {
+        let rendered = render_template(r#"{"matrix_a":[[1]]} {x} {} {CURRENT_PROMPT}"#, &[("CURRENT_PROMPT", "ok")], &[])
+            .unwrap();
+        assert_eq!(rendered, r#"{"matrix_a":[[1]]} {x} {} ok"#);
+    }
+
+    #[test]
+    fn test_unknown_and_missing_placeholders() {
+        let err = render_template("{CURRENT_PROMT}", &[("CURRENT_PROMPT", "x")], &["CURRENT_PROMPT"])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown placeholder(s) {CURRENT_PROMT}"));
+        assert!(err.contains("missing placeholder(s) {CURRENT_PROMPT}"));
+    }
+}
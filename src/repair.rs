@@ -0,0 +1,64 @@
+use crate::backend::Message;
+use crate::convert::convert_reply;
+use crate::rubric::Rubric;
+
+/// One request/reply round of a repair session and what was wrong with the reply.
+#[derive(Clone, Debug)]
+pub struct RepairAttempt {
+    pub request: String,
+    pub reply: String,
+    pub problems: Vec<String>,
+}
+
+/// Everything that makes `reply` unusable: no assessment found, a parse or validation error,
+/// or categories over `max_points`. Empty when the reply converts cleanly.
+pub fn reply_problems(reply: &str, rubric: &Rubric, max_points: Option<usize>) -> Vec<String> {
+    match (convert_reply(reply, rubric, max_points), max_points) {
+        (Err(e), _) => vec![format!("{:#}", e)],
+        (Ok(conversion), Some(max_points)) => conversion.too_long.iter()
+            .map(|name| format!("{} has more than {} points", name, max_points))
+            .collect(),
+        (Ok(_), None) => Vec::new(),
+    }
+}
+
+/// The chat so far, each attempt's request followed by its reply, ending with `next_request`.
+pub fn conversation(attempts: &[RepairAttempt], next_request: &str) -> Vec<Message> {
+    attempts.iter()
+        .flat_map(|attempt| [Message::user(&attempt.request), Message::assistant(&attempt.reply)])
+        .chain([Message::user(next_request)])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reply_problems() {
+        let rubric = Rubric::builtin();
+        let valid = "# Experience\n- a\n- Rating: Easy\n# Knowledge\n- b\n- Rating: Medium\n\
+                     # Ambiguity\n- c\n- d\n- Rating: Easy\n# Complexity\n- e\n- Rating: Hard\n\
+                     Overall:\n  Difficulty Medium";
+        assert!(reply_problems(valid, &rubric, None).is_empty());
+        assert_eq!(reply_problems(valid, &rubric, Some(1)), vec!["Ambiguity has more than 1 points"]);
+
+        let problems = reply_problems("Sorry, I cannot rate this.", &rubric, None);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("No assessment found"));
+    }
+
+    #[test]
+    fn test_conversation() {
+        let attempts = [RepairAttempt {
+            request: "rate this".to_string(),
+            reply: "bad".to_string(),
+            problems: vec!["broken".to_string()],
+        }];
+        assert_eq!(conversation(&attempts, "again"), vec![
+            Message::user("rate this"),
+            Message::assistant("bad"),
+            Message::user("again"),
+        ]);
+    }
+}
@@ -1,43 +1,134 @@
 
 use anyhow::Result;
+use crate::assessment::Assessment;
 use crate::prompt;
-use crate::raw_example;
+use crate::few_shot::{self, Example};
+use crate::rubric::{self, Rubric};
 
-pub fn gen_request_content(current_prompt: String, previous_turn: String, preference_difficulty: usize,
-                           preference_length: usize, continuous: bool) -> Result<String> {
+/// Most points per category allowed by a length preference (0 Short, 1 Normal, 2 Long).
+/// Short asks for 2 but allows 3; Long has no limit.
+pub fn max_points(preference_length: usize) -> Option<usize> {
+    match preference_length {
+        0 => Some(3),
+        2 => None,
+        _ => Some(5),
+    }
+}
+
+/// 1-based index of the named overall level (case-insensitive), 0 for no preference.
+pub fn difficulty_preference(rubric: &Rubric, name: Option<&str>) -> Result<usize> {
+    let Some(name) = name.map(str::trim).filter(|name| !name.is_empty()) else {
+        return Ok(0);
+    };
+    rubric.overall.iter()
+        .position(|level| level.name.eq_ignore_ascii_case(name))
+        .map(|i| i + 1)
+        .ok_or_else(|| anyhow::anyhow!(
+            "Unknown difficulty \"{}\", expected one of: {}", name, rubric.overall_names().join(", ")
+        ))
+}
+
+/// Length preference for `Short`, `Normal` or `Long` (case-insensitive), Short when empty.
+pub fn length_preference(name: &str) -> Result<usize> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "" | "short" => Ok(0),
+        "normal" => Ok(1),
+        "long" => Ok(2),
+        _ => Err(anyhow::anyhow!("Unknown length \"{}\", expected one of: Short, Normal, Long", name.trim())),
+    }
+}
+
+/// Follow-up asking the LLM to shorten the categories that broke the point limit, quoting its
+/// own answer so the request also works in a fresh chat.
+pub fn gen_trim_request(assessment: &Assessment, too_long: &[&str], max_points: usize) -> String {
+    format!("Some categories have more points than I asked for: {}.\n\
+    Please shorten each of them to no more than {} points, keeping the most important ones \
+    and keeping every rating as it is. Reply with the full assessment in the same format.\n\
+    Your previous answer:\n{}", too_long.join(", "), max_points, assessment.to_markdown(true))
+}
+
+/// Follow-up for a reply that could not be used, built on the template's continuous text so
+/// it reads as the next turn of the same chat. The problems are quoted as they were reported.
+pub fn gen_repair_request(template: &prompt::PromptTemplate, rubric: &Rubric, current_prompt: String,
+                          previous_turn: String, problems: &[String]) -> Result<String> {
+    let mut repair_request = String::from("Your previous answer could not be used:\n");
+    for problem in problems {
+        repair_request.push_str(&format!("- {}\n", problem.replace('\n', "\n  ")));
+    }
+    repair_request.push_str(&format!("Please answer again and fix these problems. Use only the ratings {} \
+    and keep the same format.\n", rubric::join_list(&rubric.ratings.iter().map(String::as_str).collect::<Vec<_>>(), "or")));
+    repair_request.push_str(&prompt::generate_chat_gpt_prompt_continuous(template, rubric, current_prompt, previous_turn)?);
+    Ok(repair_request)
+}
+
+/// The request to send to the LLM. `continuous` picks the template's short follow-up text
+/// instead of the full one, which also carries the `examples`. `preference_difficulty` is 0 for
+/// no preference or the 1-based index of a rubric overall level; `preference_length` is 0 Short,
+/// 1 Normal or 2 Long, see `max_points`.
+#[allow(clippy::too_many_arguments)]
+pub fn gen_request_content(template: &prompt::PromptTemplate, rubric: &Rubric, current_prompt: String,
+                           previous_turn: String, preference_difficulty: usize, preference_length: usize,
+                           examples: &[Example], continuous: bool) -> Result<String> {
+    gen_request_parts(template, rubric, current_prompt, previous_turn, preference_difficulty,
+                      preference_length, examples, continuous).map(|parts| parts.join())
+}
+
+/// The sections `gen_request_content` is made of, in the order they are joined.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestParts {
+    /// The template filled with the rubric, the prompt and the previous turn.
+    pub body: String,
+    /// Empty for the continuous request.
+    pub examples: String,
+    pub preferences: String,
+}
+
+impl RequestParts {
+    pub fn join(self) -> String {
+        let mut request = self.body;
+        request.push_str(&self.examples);
+        request.push_str(&self.preferences);
+        request
+    }
+}
+
+/// Same as `gen_request_content`, with the sections kept apart.
+#[allow(clippy::too_many_arguments)]
+pub fn gen_request_parts(template: &prompt::PromptTemplate, rubric: &Rubric, current_prompt: String,
+                         previous_turn: String, preference_difficulty: usize, preference_length: usize,
+                         examples: &[Example], continuous: bool) -> Result<RequestParts> {
     if current_prompt.is_empty() {
         return Err(anyhow::anyhow!("Prompt cannot be empty."));
     }
-    let mut chat_gpt_prompt = if !continuous {
-        prompt::generate_chat_gpt_prompt(current_prompt, previous_turn)
+    let body = if !continuous {
+        prompt::generate_chat_gpt_prompt(template, rubric, current_prompt, previous_turn)?
     }else{
-        prompt::generate_chat_gpt_prompt_continuous(current_prompt, previous_turn)
+        prompt::generate_chat_gpt_prompt_continuous(template, rubric, current_prompt, previous_turn)?
     };
 
-    if !continuous {
-        chat_gpt_prompt.push_str(&raw_example::generate_sample());
-    }
-
+    let examples = if !continuous {
+        few_shot::render_examples(examples)
+    } else {
+        String::new()
+    };
 
+    let mut preferences = String::new();
 
-    if preference_difficulty > 0 {
+    // 0 means no preference, otherwise it is the 1-based index of the rubric's overall level
+    if let Some(level) = preference_difficulty.checked_sub(1).and_then(|i| rubric.overall.get(i)) {
 
-        chat_gpt_prompt.push_str(&format!("\nI do have a preference for the overall rating of {}\n\
+        preferences.push_str(&format!("\nI do have a preference for the overall rating of {}\n\
         So you are welcome to weak your words to get that overall rating. \
         That is the overall rating, not the component rating, so feel free to wiggle the component rating
         if possible to make it sounds fair.
         Of course, being reasonable is important, so if you tried hard but cannot, it's fine.\
-        ",  match preference_difficulty {
-            1 => "Easy",
-            2 => "Medium",
-            _ => "Hard",
-        }));
+        ",  level.name));
     }
 
-    chat_gpt_prompt.push_str("Avoid if possible putting all 4 sub rating to be the same thing.\
-    That does not sound like a subjective judgement\n");
+    preferences.push_str(&format!("Avoid if possible putting all {} sub rating to be the same thing.\
+    That does not sound like a subjective judgement\n", rubric.categories.len()));
 
-    chat_gpt_prompt.push_str(match preference_length {
+    preferences.push_str(match preference_length {
         0 => "\nFinally. I would like a simple answer, so I strongly prefer no more than 2 points \
         per category, as the absolute max should be 3. Also, if you can, please put 1\n",
         2 => "\nFinally. I would like a long answer, so feel free to add  as many point as possible\
@@ -45,21 +136,46 @@ pub fn gen_request_content(current_prompt: String, previous_turn: String, prefer
         _ => "\nFinally. I would like a simple answer, so I absolutely \
         want no more than 5 points per category, and most category should be between 2-3 points\n",
     });
-    Ok(chat_gpt_prompt)
+    Ok(RequestParts { body, examples, preferences })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_preferences() {
+        let rubric = Rubric::builtin();
+        assert_eq!(difficulty_preference(&rubric, None).unwrap(), 0);
+        assert_eq!(difficulty_preference(&rubric, Some(" ")).unwrap(), 0);
+        assert_eq!(difficulty_preference(&rubric, Some("hard")).unwrap(), 3);
+        assert!(difficulty_preference(&rubric, Some("Impossible")).is_err());
+        assert_eq!(length_preference("Long").unwrap(), 2);
+        assert!(length_preference("tiny").is_err());
+    }
+
+    #[test]
+    fn test_repair_request() {
+        let template = prompt::PromptTemplate::builtin();
+        let problems = vec!["Ratings outside the rubric scale:\nExperience: Impossible".to_string()];
+        let repair_request = gen_repair_request(&template, &Rubric::builtin(), "gen hello world".to_string(),
+                                                String::new(), &problems).unwrap();
+        assert!(repair_request.starts_with("Your previous answer could not be used:\n\
+        - Ratings outside the rubric scale:\n  Experience: Impossible\n"));
+        assert!(repair_request.contains("Do the same thing"));
+        assert!(repair_request.contains("gen hello world"));
+    }
+
     #[test]
     fn test_local_data() {
-        match gen_request_content("gen hello world".to_string(), "".to_string(), 0, 0, false) {
+        let template = prompt::PromptTemplate::builtin();
+        match gen_request_content(&template, &Rubric::builtin(), "gen hello world".to_string(), "".to_string(), 0, 0,
+                                  &few_shot::builtin_examples(), false) {
             Ok(r) => {
                 println!("{}", r);
             }
             Err(e) => {
-                println!("{}", format!("{:?}", e));
+                println!("{:?}", e);
             }
         }
     }
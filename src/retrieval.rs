@@ -0,0 +1,133 @@
+use std::collections::{HashMap, HashSet};
+
+/// BM25 term frequency saturation.
+const K1: f64 = 1.2;
+/// BM25 document length normalisation.
+const B: f64 = 0.75;
+
+/// Words too common to say anything about what a text is about.
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "can", "do", "does", "for", "from",
+    "have", "how", "i", "if", "in", "is", "it", "its", "me", "my", "no", "not", "of", "on", "or",
+    "please", "so", "that", "the", "then", "there", "this", "to", "was", "we", "what", "when",
+    "which", "will", "with", "you", "your",
+];
+
+/// Lowercase words of `text`, without stop words and single characters. Code is split on
+/// punctuation, so `serde_json::to_string` gives `serde`, `json` and `string`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() > 1)
+        .map(str::to_lowercase)
+        .filter(|word| !STOP_WORDS.contains(&word.as_str()))
+        .collect()
+}
+
+/// A document that matched a query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    /// Position of the document in the indexed list.
+    pub index: usize,
+    pub score: f64,
+    /// Query words found in the document, the ones that weigh most first.
+    pub terms: Vec<String>,
+}
+
+/// Okapi BM25 index over a fixed list of documents, built and searched in memory.
+pub struct Bm25 {
+    documents: Vec<HashMap<String, usize>>,
+    lengths: Vec<usize>,
+    average_length: f64,
+    document_frequency: HashMap<String, usize>,
+}
+
+impl Bm25 {
+    pub fn new<S: AsRef<str>>(documents: &[S]) -> Self {
+        let mut term_counts = Vec::new();
+        let mut lengths = Vec::new();
+        let mut document_frequency = HashMap::new();
+        for document in documents {
+            let words = tokenize(document.as_ref());
+            let mut counts = HashMap::new();
+            for word in &words {
+                *counts.entry(word.clone()).or_insert(0) += 1;
+            }
+            for word in counts.keys() {
+                *document_frequency.entry(word.clone()).or_insert(0) += 1;
+            }
+            lengths.push(words.len());
+            term_counts.push(counts);
+        }
+        let average_length = if lengths.is_empty() {
+            0.0
+        } else {
+            lengths.iter().sum::<usize>() as f64 / lengths.len() as f64
+        };
+        Self { documents: term_counts, lengths, average_length, document_frequency }
+    }
+
+    /// The `k` documents that score highest for `query`, best first. Documents sharing no word
+    /// with the query are left out; ties keep the document order.
+    pub fn search(&self, query: &str, k: usize) -> Vec<Hit> {
+        let mut query_words = tokenize(query);
+        let mut seen = HashSet::new();
+        query_words.retain(|word| seen.insert(word.clone()));
+
+        let mut hits: Vec<Hit> = self.documents.iter()
+            .enumerate()
+            .filter_map(|(index, counts)| {
+                let mut terms: Vec<(f64, &String)> = query_words.iter()
+                    .filter_map(|word| Some((self.term_score(index, word, *counts.get(word)?), word)))
+                    .collect();
+                if terms.is_empty() {
+                    return None;
+                }
+                terms.sort_by(|a, b| b.0.total_cmp(&a.0));
+                Some(Hit {
+                    index,
+                    score: terms.iter().map(|(score, _)| score).sum(),
+                    terms: terms.into_iter().map(|(_, word)| word.clone()).collect(),
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(k);
+        hits
+    }
+
+    fn term_score(&self, index: usize, word: &str, count: usize) -> f64 {
+        let n = self.documents.len() as f64;
+        let df = self.document_frequency.get(word).copied().unwrap_or(0) as f64;
+        let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+        let tf = count as f64;
+        let length_ratio = if self.average_length > 0.0 { self.lengths[index] as f64 / self.average_length } else { 1.0 };
+        idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * length_ratio))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(tokenize("How do I call serde_json::to_string in Rust 2021?"), vec!["call", "serde", "json", "string", "rust", "2021"]);
+    }
+
+    #[test]
+    fn test_search() {
+        let index = Bm25::new(&[
+            "Debug a Telnet connection error in a Rust server",
+            "Write a SQL query joining two tables",
+            "Sort a list of dictionaries in Python by a key",
+            "Python script that reads a CSV file and writes SQL inserts",
+        ]);
+        let hits = index.search("My python code fails to sort the list", 3);
+        assert_eq!(hits.iter().map(|hit| hit.index).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(hits[0].terms, vec!["sort", "list", "python"]);
+        assert!(hits[0].score > hits[1].score);
+
+        assert_eq!(index.search("SQL", 1)[0].index, 1);
+        assert!(index.search("javascript", 3).is_empty());
+    }
+}
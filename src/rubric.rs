@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::rating::{rating_key, Rating};
+
+const BUILTIN_RUBRIC: &str = include_str!("../rubric.yaml");
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RubricCategory {
+    pub name: String,
+    pub label: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OverallLevel {
+    pub name: String,
+    #[serde(default)]
+    pub guide: HashMap<String, String>,
+}
+
+/// Categories, rating scale and overall levels of the assessment. This is the single
+/// definition the request text and the answer checks are built from.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Rubric {
+    pub categories: Vec<RubricCategory>,
+    pub ratings: Vec<String>,
+    pub overall: Vec<OverallLevel>,
+}
+
+impl Rubric {
+    /// The rubric shipped with the tool, the same as the repository's `rubric.yaml`.
+    pub fn builtin() -> Self {
+        Self::from_yaml(BUILTIN_RUBRIC).expect("built-in rubric must be valid")
+    }
+
+    /// Parse and validate a rubric in the `rubric.yaml` format.
+    pub fn from_yaml(yaml_str: &str) -> Result<Self> {
+        let rubric: Rubric = serde_yaml::from_str(yaml_str)
+            .map_err(|e| anyhow!("Invalid rubric format: {}", e))?;
+        rubric.validate()?;
+        Ok(rubric)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.categories.is_empty() {
+            return Err(anyhow!("Rubric must define at least one category"));
+        }
+        if self.ratings.is_empty() {
+            return Err(anyhow!("Rubric must define at least one rating"));
+        }
+        if self.overall.is_empty() {
+            return Err(anyhow!("Rubric must define at least one overall level"));
+        }
+
+        let category_names: Vec<&str> = self.categories.iter().map(|c| c.name.as_str()).collect();
+        let overall_names: Vec<&str> = self.overall.iter().map(|l| l.name.as_str()).collect();
+        for (what, names) in [
+            ("category", &category_names),
+            ("rating", &self.ratings.iter().map(String::as_str).collect()),
+            ("overall level", &overall_names),
+        ] {
+            if let Some(dup) = names.iter().enumerate().find(|(i, n)| names[..*i].contains(n)) {
+                return Err(anyhow!("Duplicate {} \"{}\" in rubric", what, dup.1));
+            }
+        }
+
+        if self.labels().any(|label| rating_key(label).is_empty()) {
+            return Err(anyhow!("Rubric ratings and overall levels must not be blank"));
+        }
+
+        for level in &self.overall {
+            if let Some(unknown) = level.guide.keys().find(|k| !category_names.contains(&k.as_str())) {
+                return Err(anyhow!(
+                    "Overall level \"{}\" has a guide for unknown category \"{}\"", level.name, unknown
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn category_names(&self) -> Vec<&str> {
+        self.categories.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    pub fn overall_names(&self) -> Vec<&str> {
+        self.overall.iter().map(|l| l.name.as_str()).collect()
+    }
+
+    /// The rubric's own label for a rating the LLM wrote: one of the ratings or overall levels,
+    /// however its case, spacing and dashes are written. Wording this rubric does not use is
+    /// mapped onto the built-in levels with `Rating::normalize`, for `Assessment::validate` to
+    /// check against the scale.
+    pub fn normalize_rating(&self, text: &str) -> Result<String> {
+        let key = rating_key(text);
+        if let Some(label) = self.labels().find(|label| rating_key(label) == key) {
+            return Ok(label.to_string());
+        }
+        Rating::normalize(text)
+            .map(|rating| rating.as_str().to_string())
+            .map_err(|_| anyhow!("Unknown rating \"{}\", expected one of: {}", text.trim(), self.ratings.join(", ")))
+    }
+
+    /// Every rating and overall level.
+    fn labels(&self) -> impl Iterator<Item = &str> {
+        self.ratings.iter().map(String::as_str).chain(self.overall_names())
+    }
+
+    /// Position of `label` on the rating scale, 0 for the easiest rating. `None` for a label
+    /// that is not one of the ratings, such as an overall level only.
+    pub fn rating_step(&self, label: &str) -> Option<usize> {
+        self.ratings.iter().position(|rating| rating == label)
+    }
+
+    /// Per-level guide, one line per category, in the layout the request text has always used.
+    pub fn guide(&self) -> String {
+        let mut guide = String::new();
+        for level in &self.overall {
+            guide.push_str(&format!("{}:\n\n", level.name));
+            for category in &self.categories {
+                if let Some(text) = level.guide.get(&category.name) {
+                    guide.push_str(&format!("{}: {}\n", category.label, text));
+                }
+            }
+            guide.push_str("\n\n");
+        }
+        guide
+    }
+
+    /// The same rubric with each guide text cut to its first clause, for requests that have to
+    /// fit a small context. Categories, ratings and levels are kept, so answers check the same.
+    pub fn compressed(&self) -> Rubric {
+        let mut rubric = self.clone();
+        for level in &mut rubric.overall {
+            for text in level.guide.values_mut() {
+                if let Some(end) = text.find([',', ';', '(', '.']) {
+                    text.truncate(end);
+                    text.truncate(text.trim_end().len());
+                }
+            }
+        }
+        rubric
+    }
+}
+
+/// Rubric file to read: `$QAG_RUBRIC`, or `rubric.yaml` relative to the working directory.
+pub fn rubric_path() -> PathBuf {
+    env::var_os("QAG_RUBRIC")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("rubric.yaml"))
+}
+
+/// Rubric defined in `path`, or the built-in one if the file does not exist.
+pub fn load_rubric(path: &Path) -> Result<Rubric> {
+    if !path.exists() {
+        return Ok(Rubric::builtin());
+    }
+    let yaml_str = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Rubric::from_yaml(&yaml_str)
+        .with_context(|| format!("Invalid rubric {}", path.display()))
+}
+
+/// "a, b and c" style list used in the request text.
+pub fn join_list(items: &[&str], last_separator: &str) -> String {
+    match items {
+        [] => String::new(),
+        [only] => only.to_string(),
+        [rest @ .., last] => format!("{} {} {}", rest.join(", "), last_separator, last),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_rubric() {
+        let rubric = Rubric::builtin();
+        assert_eq!(rubric.category_names(), vec!["Experience", "Knowledge", "Ambiguity", "Complexity"]);
+        assert_eq!(rubric.ratings.len(), 6);
+        assert_eq!(join_list(&rubric.overall_names(), "or"), "Easy, Medium or Hard");
+        assert!(rubric.guide().starts_with("Easy:\n\nExperience level: undergraduate level\n"));
+    }
+
+    #[test]
+    fn test_guide_for_unknown_category_is_rejected() {
+        let err = Rubric::from_yaml("
+categories: [{name: Experience, label: Experience level}]
+ratings: [Easy]
+overall: [{name: Easy, guide: {Security: none}}]
+").unwrap_err();
+        assert!(err.to_string().contains("unknown category \"Security\""));
+    }
+
+    #[test]
+    fn test_custom_levels() {
+        let rubric = Rubric::from_yaml("
+categories: [{name: Security, label: Attack surface}]
+ratings: [Trivial, Routine, Tricky, Research]
+overall: [{name: Routine}, {name: Research}]
+").unwrap();
+        assert_eq!(rubric.normalize_rating(" **routine** ").unwrap(), "Routine");
+        // A word of the built-in scale goes through as its canonical label, for validation to reject
+        assert_eq!(rubric.normalize_rating("Moderate").unwrap(), "Medium");
+        assert_eq!(rubric.rating_step("Tricky"), Some(2));
+        let err = rubric.normalize_rating("Impossible").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown rating \"Impossible\", expected one of: Trivial, Routine, Tricky, Research");
+    }
+
+    #[test]
+    fn test_compressed() {
+        let rubric = Rubric::builtin();
+        let compressed = rubric.compressed();
+        assert_eq!(compressed.overall[0].guide["Knowledge"], "limited domain/algorithmics knowledge or implementation context");
+        assert_eq!(compressed.overall[1].guide["Experience"], "masters level");
+        assert!(compressed.guide().len() < rubric.guide().len() * 2 / 3);
+        compressed.validate().unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use anyhow::Result;
+use crate::backend::Message;
+use crate::prompt::PromptTemplate;
+use crate::rubric::Rubric;
+
+/// Why the next request has to carry the full rubric.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FullReason {
+    /// Nothing was sent yet, or the session was reset.
+    NewSession,
+    /// The chat holds another template or rubric than the one selected.
+    Changed,
+    /// The session reached its turn limit; the rubric is repeated before the model loses track.
+    TurnLimit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestKind {
+    Full(FullReason),
+    /// The template's short `next` text, for a chat that already holds the full request.
+    Continuous,
+}
+
+impl RequestKind {
+    pub fn is_continuous(&self) -> bool {
+        *self == RequestKind::Continuous
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            RequestKind::Full(FullReason::NewSession) => "full request (new chat)",
+            RequestKind::Full(FullReason::Changed) => "full request (template or rubric changed)",
+            RequestKind::Full(FullReason::TurnLimit) => "full request (turn limit reached)",
+            RequestKind::Continuous => "short follow-up",
+        }
+    }
+}
+
+/// A request built by `Session::start_turn`, to hand back to `finish_turn` once it was sent.
+#[derive(Clone, Debug)]
+pub struct Turn {
+    pub request: String,
+    pub kind: RequestKind,
+    version: u64,
+}
+
+/// Tracks what one chat or backend conversation already holds, so the full request is only
+/// sent when it has to be.
+#[derive(Clone, Debug)]
+pub struct Session {
+    /// Version of the template and rubric last sent in full, `None` before the first request.
+    version: Option<u64>,
+    /// Turns sent since the last full request, that one included.
+    turns: usize,
+    /// Turns after which the full request is sent again, 0 for never.
+    pub max_turns: usize,
+    /// Earlier requests and replies, for backends that need the whole conversation resent.
+    history: Vec<Message>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new(10)
+    }
+}
+
+impl Session {
+    pub fn new(max_turns: usize) -> Self {
+        Self {
+            version: None,
+            turns: 0,
+            max_turns,
+            history: Vec::new(),
+        }
+    }
+
+    /// Start over, as for a new chat: the next request is the full one.
+    pub fn reset(&mut self) {
+        self.version = None;
+        self.turns = 0;
+        self.history.clear();
+    }
+
+    pub fn turns(&self) -> usize {
+        self.turns
+    }
+
+    /// Conversation so far, oldest first. Only turns finished with a reply are kept.
+    pub fn history(&self) -> &[Message] {
+        &self.history
+    }
+
+    /// Everything to send for `turn`: the history when the turn continues the conversation, then
+    /// `conversation`, the turn's own request and repair exchanges. A full request starts the
+    /// conversation over, so it goes out without the history.
+    pub fn messages(&self, turn: &Turn, conversation: Vec<Message>) -> Vec<Message> {
+        let mut messages = if turn.kind.is_continuous() { self.history.clone() } else { Vec::new() };
+        messages.extend(conversation);
+        messages
+    }
+
+    /// Which request the next turn with `template` and `rubric` needs.
+    pub fn next_kind(&self, template: &PromptTemplate, rubric: &Rubric) -> RequestKind {
+        match self.version {
+            None => RequestKind::Full(FullReason::NewSession),
+            Some(version) if version != version_of(template, rubric) => RequestKind::Full(FullReason::Changed),
+            Some(_) if self.max_turns > 0 && self.turns >= self.max_turns => RequestKind::Full(FullReason::TurnLimit),
+            Some(_) => RequestKind::Continuous,
+        }
+    }
+
+    /// Build the next request with `build`, which is told whether it should be the continuous
+    /// one as `next_kind` decides. The session is only updated by `finish_turn`, so a request
+    /// that fails to send leaves it as it was.
+    pub fn start_turn(&self, template: &PromptTemplate, rubric: &Rubric,
+                      build: impl FnOnce(bool) -> Result<String>) -> Result<Turn> {
+        let kind = self.next_kind(template, rubric);
+        let request = build(kind.is_continuous())?;
+        Ok(Turn { request, kind, version: version_of(template, rubric) })
+    }
+
+    /// Record a sent request. A full request starts the conversation over. `reply` is kept
+    /// in the history when known; a copied request has none.
+    pub fn finish_turn(&mut self, turn: Turn, reply: Option<&str>) {
+        if !turn.kind.is_continuous() {
+            self.history.clear();
+            self.turns = 0;
+        }
+        self.version = Some(turn.version);
+        self.turns += 1;
+        if let Some(reply) = reply {
+            self.history.push(Message::user(&turn.request));
+            self.history.push(Message::assistant(reply));
+        }
+    }
+}
+
+/// Identifies the template and rubric texts, so an edited and reloaded file counts as a change.
+fn version_of(template: &PromptTemplate, rubric: &Rubric) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (&template.name, &template.original, &template.next).hash(&mut hasher);
+    // Through a JSON value, whose maps are sorted, as the guides are hash maps
+    serde_json::to_value(rubric).map(|value| value.to_string()).unwrap_or_default().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request;
+
+    fn send(session: &mut Session, template: &PromptTemplate, reply: Option<&str>) -> RequestKind {
+        let rubric = Rubric::builtin();
+        let turn = session.start_turn(template, &rubric, |continuous| request::gen_request_content(
+            template, &rubric, "Sort a list".to_string(), String::new(), 0, 0, &[], continuous,
+        )).unwrap();
+        let kind = turn.kind;
+        assert_eq!(turn.request.starts_with("Do the same thing"), kind.is_continuous());
+        session.finish_turn(turn, reply);
+        kind
+    }
+
+    #[test]
+    fn test_session() {
+        let template = PromptTemplate::builtin();
+        let mut session = Session::new(3);
+        assert_eq!(send(&mut session, &template, Some("first")), RequestKind::Full(FullReason::NewSession));
+        assert_eq!(send(&mut session, &template, Some("second")), RequestKind::Continuous);
+        assert_eq!(session.history().len(), 4);
+        assert_eq!(session.history()[3], Message::assistant("second"));
+        assert_eq!(send(&mut session, &template, None), RequestKind::Continuous);
+        assert_eq!(session.history().len(), 4);
+
+        // Every fourth request repeats the rubric and starts the conversation over
+        assert_eq!(send(&mut session, &template, Some("fourth")), RequestKind::Full(FullReason::TurnLimit));
+        assert_eq!(session.turns(), 1);
+        assert_eq!(session.history().len(), 2);
+
+        let mut edited = template.clone();
+        edited.next.push_str("Be brief.\n");
+        assert_eq!(session.next_kind(&edited, &Rubric::builtin()), RequestKind::Full(FullReason::Changed));
+        assert_eq!(send(&mut session, &edited, None), RequestKind::Full(FullReason::Changed));
+        assert_eq!(send(&mut session, &edited, None), RequestKind::Continuous);
+
+        session.reset();
+        assert!(session.history().is_empty());
+        assert_eq!(send(&mut session, &edited, None), RequestKind::Full(FullReason::NewSession));
+    }
+
+    #[test]
+    fn test_messages() {
+        let template = PromptTemplate::builtin();
+        let rubric = Rubric::builtin();
+        let mut session = Session::new(2);
+        send(&mut session, &template, Some("first"));
+        let build = |continuous| request::gen_request_content(
+            &template, &rubric, "Sort a list".to_string(), String::new(), 0, 0, &[], continuous,
+        );
+
+        let turn = session.start_turn(&template, &rubric, build).unwrap();
+        assert_eq!(turn.kind, RequestKind::Continuous);
+        let messages = session.messages(&turn, vec![Message::user(&turn.request)]);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1], Message::assistant("first"));
+        session.finish_turn(turn, Some("second"));
+
+        // The turn limit starts over: the old exchanges are not sent with the full request
+        let turn = session.start_turn(&template, &rubric, build).unwrap();
+        assert_eq!(turn.kind, RequestKind::Full(FullReason::TurnLimit));
+        assert_eq!(session.history().len(), 4);
+        let messages = session.messages(&turn, vec![Message::user(&turn.request)]);
+        assert_eq!(messages, vec![Message::user(&turn.request)]);
+    }
+
+    #[test]
+    fn test_unlimited_turns() {
+        let template = PromptTemplate::builtin();
+        let mut session = Session::new(0);
+        send(&mut session, &template, None);
+        for _ in 0..20 {
+            assert_eq!(send(&mut session, &template, None), RequestKind::Continuous);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Result};
+use tokenizers::Tokenizer;
+use crate::budget::estimate_tokens;
+
+/// Counts tokens with a model's Hugging Face `tokenizer.json`, or estimates them without one.
+#[derive(Clone, Debug, Default)]
+pub struct TokenCounter {
+    tokenizer: Option<(PathBuf, Box<Tokenizer>)>,
+}
+
+impl TokenCounter {
+    /// The counter `estimate_tokens` gives.
+    pub fn estimate() -> Self {
+        Self::default()
+    }
+
+    /// Load the `tokenizer.json` at `path`. Nothing is downloaded: the file has to be local.
+    pub fn load(path: &Path) -> Result<Self> {
+        let tokenizer = Tokenizer::from_file(path)
+            .map_err(|e| anyhow!("Failed to load the tokenizer {}: {}", path.display(), e))?;
+        Ok(Self { tokenizer: Some((path.to_path_buf(), Box::new(tokenizer))) })
+    }
+
+    /// The tokenizer at `path`, or the estimate for `None`.
+    pub fn from_setting(path: Option<&Path>) -> Result<Self> {
+        path.map_or_else(|| Ok(Self::estimate()), Self::load)
+    }
+
+    /// Path of the loaded tokenizer, `None` when the tokens are estimated.
+    pub fn path(&self) -> Option<&Path> {
+        self.tokenizer.as_ref().map(|(path, _)| path.as_path())
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.tokenizer.is_some()
+    }
+
+    /// Tokens of `text`, without the special tokens a chat template adds around it. A text the
+    /// tokenizer fails on is estimated.
+    pub fn count(&self, text: &str) -> usize {
+        match &self.tokenizer {
+            Some((_, tokenizer)) => tokenizer.encode(text, false)
+                .map(|encoding| encoding.len())
+                .unwrap_or_else(|_| estimate_tokens(text)),
+            None => estimate_tokens(text),
+        }
+    }
+
+    /// How the counts were made, to show next to them.
+    pub fn describe(&self) -> String {
+        match self.path() {
+            Some(path) => format!("exact, {}", path.display()),
+            None => "estimated".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::test_dir::TestDir;
+
+    /// A word-level tokenizer knowing a handful of words, split on whitespace and punctuation.
+    const TOKENIZER_JSON: &str = r#"{
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [],
+        "normalizer": null,
+        "pre_tokenizer": {"type": "BertPreTokenizer"},
+        "post_processor": null,
+        "decoder": null,
+        "model": {
+            "type": "WordLevel",
+            "vocab": {"[UNK]": 0, "merge": 1, "two": 2, "sorted": 3, "lists": 4, ".": 5},
+            "unk_token": "[UNK]"
+        }
+    }"#;
+
+    #[test]
+    fn test_token_counter() {
+        let dir = TestDir::new("tokenizer");
+        let path = dir.join("tokenizer.json");
+        fs::write(&path, TOKENIZER_JSON).unwrap();
+        let counter = TokenCounter::load(&path).unwrap();
+
+        assert!(counter.is_exact());
+        assert_eq!(counter.count("merge two sorted lists."), 5);
+        assert_eq!(counter.count("Merge  unknown,lists"), 4);
+        assert!(counter.describe().starts_with("exact, "));
+
+        let estimate = TokenCounter::from_setting(None).unwrap();
+        assert_eq!(estimate.count("Hello, world!"), estimate_tokens("Hello, world!"));
+        assert!(TokenCounter::load(Path::new("/nonexistent/tokenizer.json")).is_err());
+    }
+}